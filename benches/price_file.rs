@@ -0,0 +1,111 @@
+/*!
+ * Memory profile of reading a large price file.
+ *
+ * Generates an archival price file (decades of daily prices for a few hundred symbols)
+ * and compares the peak heap usage and the duration of loading all the records
+ * against streaming queries.
+ *
+ * Run with `cargo bench --bench price_file`.
+ */
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fs::File,
+    io::{BufWriter, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use chrono::{Duration, NaiveDate};
+use pricedb::price_flat_file::{load_records, PriceReader};
+
+const SYMBOLS: usize = 200;
+const DAYS: i64 = 2_500;
+
+/// Tracks the current and the peak heap usage.
+struct CountingAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let dir = tempfile::tempdir().expect("temp dir");
+    let path = dir.path().join("archive.txt");
+    let path = path.to_str().expect("path");
+
+    let lines = generate_file(path);
+    println!("{lines} prices in {path}\n");
+
+    measure("load all records", || load_records(path).expect("loaded").len());
+
+    measure("latest per symbol", || {
+        PriceReader::open(path)
+            .expect("opened")
+            .latest()
+            .expect("parsed")
+            .len()
+    });
+
+    let from = NaiveDate::from_ymd_opt(2020, 1, 1);
+    let to = NaiveDate::from_ymd_opt(2020, 12, 31);
+    measure("date range", || {
+        PriceReader::open(path)
+            .expect("opened")
+            .between(from, to)
+            .filter(|record| record.is_ok())
+            .count()
+    });
+}
+
+/// Runs the operation and prints the peak heap usage during it.
+fn measure(name: &str, operation: impl FnOnce() -> usize) {
+    let baseline = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+    let started = Instant::now();
+
+    let count = operation();
+
+    let elapsed = started.elapsed();
+    let peak = PEAK.load(Ordering::Relaxed) - baseline;
+    println!(
+        "{name:<20} {count:>9} records  {:>10.1} KiB peak  {:>8.0?}",
+        peak as f64 / 1024.0,
+        elapsed
+    );
+}
+
+fn generate_file(path: &str) -> usize {
+    let mut writer = BufWriter::new(File::create(path).expect("created"));
+    let start = NaiveDate::from_ymd_opt(2014, 1, 1).unwrap();
+    let mut lines = 0;
+
+    for day in 0..DAYS {
+        let date = start + Duration::days(day);
+        for symbol in 0..SYMBOLS {
+            let value = 100 + (day as usize * 7 + symbol * 13) % 900;
+            writeln!(writer, "P {date} SYM{symbol:03} {value}.{:02} EUR", symbol % 100)
+                .expect("written");
+            lines += 1;
+        }
+    }
+
+    lines
+}
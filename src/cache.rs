@@ -0,0 +1,275 @@
+/*!
+ * Cache of the downloaded provider responses.
+ *
+ * The responses are stored as files in the cache directory, set with `cache_path`
+ * in the configuration (defaults to `pricedb` in the temp directory). An entry is
+ * fresh for `cache_expiry_hours` after it was written, or for the provider's
+ * `cache_ttl` hours.
+ * Only the files named as the cache entries are listed and deleted, so that the
+ * directory can be shared with other files.
+ */
+use std::{
+    env::temp_dir,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate};
+
+use crate::config::PriceDbConfig;
+
+/// How the downloads use the cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CacheMode {
+    /// Use the fresh cached responses, and cache the new ones.
+    #[default]
+    Use,
+    /// Download again, and cache the new responses.
+    Refresh,
+    /// Neither read nor write the cache.
+    Off,
+}
+
+impl CacheMode {
+    /// The mode from the `--no-cache` and `--refresh` flags.
+    pub fn from_flags(no_cache: bool, refresh: bool) -> Self {
+        if no_cache {
+            Self::Off
+        } else if refresh {
+            Self::Refresh
+        } else {
+            Self::Use
+        }
+    }
+
+    pub fn reads(&self) -> bool {
+        *self == Self::Use
+    }
+
+    pub fn writes(&self) -> bool {
+        *self != Self::Off
+    }
+}
+
+/// The name prefixes of the cache entries: the Fixer rates and the cached prices.
+const ENTRY_PREFIXES: [&str; 2] = ["fixerio_", "price_"];
+
+/// A file in the cache directory.
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+impl CacheEntry {
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Age of the entry. Entries with a modification time in the future are new.
+    pub fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.modified)
+            .unwrap_or_default()
+    }
+
+    pub fn is_expired(&self, expiry: Duration) -> bool {
+        self.age() >= expiry
+    }
+}
+
+/// The configured cache directory.
+pub fn get_cache_dir(config: &PriceDbConfig) -> PathBuf {
+    if config.cache_path.is_empty() {
+        temp_dir().join("pricedb")
+    } else {
+        PathBuf::from(&config.cache_path)
+    }
+}
+
+/// The configured expiry of the cached entries.
+pub fn get_cache_expiry(config: &PriceDbConfig) -> Duration {
+    Duration::from_secs(config.cache_expiry_hours * 60 * 60)
+}
+
+/// The expiry of the cached responses of the provider (agent). Uses the provider's
+/// `cache_ttl`, if set.
+pub fn get_provider_expiry(config: &PriceDbConfig, agent: &str) -> Duration {
+    match config.cache_ttl.get(agent) {
+        Some(hours) => Duration::from_secs(hours * 60 * 60),
+        None => get_cache_expiry(config),
+    }
+}
+
+/// Lists the cached entries, sorted by name. A missing directory is empty.
+/// The other files in the directory are skipped.
+pub fn list(cache_dir: &Path) -> Result<Vec<CacheEntry>> {
+    if !cache_dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut entries = vec![];
+    let dir = fs::read_dir(cache_dir)
+        .with_context(|| format!("Could not read the cache directory {}", cache_dir.display()))?;
+
+    for dir_entry in dir {
+        let dir_entry = dir_entry?;
+        let metadata = dir_entry.metadata()?;
+        if !metadata.is_file() || !is_entry_name(&dir_entry.file_name().to_string_lossy()) {
+            continue;
+        }
+
+        entries.push(CacheEntry {
+            path: dir_entry.path(),
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        });
+    }
+
+    entries.sort_by_key(|entry| entry.name());
+
+    Ok(entries)
+}
+
+/// Whether the file is a cache entry, i.e. fixerio_EUR_2024-02-03.json.
+fn is_entry_name(name: &str) -> bool {
+    let Some((key, date)) = name.strip_suffix(".json").and_then(|stem| stem.rsplit_once('_')) else {
+        return false;
+    };
+
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok()
+        && ENTRY_PREFIXES.iter().any(|prefix| key.len() > prefix.len() && key.starts_with(prefix))
+}
+
+/// Deletes the cached entries, or only the expired ones when the expiry is given.
+/// Returns the number of deleted entries.
+pub fn clear(cache_dir: &Path, expiry: Option<Duration>) -> Result<usize> {
+    let mut count = 0;
+
+    for entry in list(cache_dir)? {
+        if expiry.is_some_and(|expiry| !entry.is_expired(expiry)) {
+            continue;
+        }
+
+        fs::remove_file(&entry.path)
+            .with_context(|| format!("Could not delete {}", entry.path.display()))?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Reads the cached content, if it exists and has not expired.
+pub fn read(path: &Path, expiry: Duration) -> Option<String> {
+    let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok()?;
+    let entry = CacheEntry {
+        path: path.to_path_buf(),
+        size: 0,
+        modified,
+    };
+
+    if entry.is_expired(expiry) {
+        log::debug!("Cache entry {} has expired", path.display());
+        return None;
+    }
+
+    fs::read_to_string(path).ok()
+}
+
+/// Saves the content into the cache, creating the directory when needed.
+pub fn write(path: &Path, content: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create the cache directory {}", dir.display()))?;
+    }
+
+    fs::write(path, content).with_context(|| format!("Could not cache {}", path.display()))
+}
+
+/// A line for the cache listing.
+pub fn format_entry(entry: &CacheEntry, expiry: Duration) -> String {
+    let modified: DateTime<Local> = entry.modified.into();
+    let status = if entry.is_expired(expiry) {
+        "expired"
+    } else {
+        "fresh"
+    };
+
+    format!(
+        "{}  {:>8}  {}  {status}",
+        modified.format("%Y-%m-%d %H:%M:%S"),
+        entry.size,
+        entry.name()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_provider_expiry() {
+        let config = PriceDbConfig {
+            cache_expiry_hours: 12,
+            cache_ttl: HashMap::from([("yahoo_finance".to_string(), 1)]),
+            ..Default::default()
+        };
+
+        assert_eq!(Duration::from_secs(3600), get_provider_expiry(&config, "yahoo_finance"));
+        assert_eq!(Duration::from_secs(43200), get_provider_expiry(&config, "fixerio"));
+    }
+
+    #[test]
+    fn test_mode_from_flags() {
+        assert_eq!(CacheMode::Use, CacheMode::from_flags(false, false));
+        assert_eq!(CacheMode::Refresh, CacheMode::from_flags(false, true));
+        assert_eq!(CacheMode::Off, CacheMode::from_flags(true, true));
+    }
+
+    #[test]
+    fn test_read_write_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache").join("fixerio_EUR_2024-02-03.json");
+
+        write(&path, "{}").unwrap();
+
+        assert_eq!(Some("{}".to_string()), read(&path, Duration::from_secs(3600)));
+        assert_eq!(None, read(&path, Duration::ZERO));
+
+        let cache_dir = path.parent().unwrap();
+        assert_eq!(1, list(cache_dir).unwrap().len());
+        // only the expired entries
+        assert_eq!(0, clear(cache_dir, Some(Duration::from_secs(3600))).unwrap());
+        assert_eq!(1, clear(cache_dir, None).unwrap());
+        assert!(list(cache_dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_clear_keeps_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path();
+        for name in [
+            "fixerio_EUR_2024-02-03.json",
+            "price_stooq_XETRA_EL4X_EUR_2024-02-03.json",
+            "prices.txt",
+            "settings.json",
+            "price_2024-02-03.json",
+        ] {
+            write(&cache_dir.join(name), "{}").unwrap();
+        }
+
+        assert_eq!(2, list(cache_dir).unwrap().len());
+        assert_eq!(2, clear(cache_dir, None).unwrap());
+        assert!(cache_dir.join("prices.txt").exists());
+        assert!(cache_dir.join("settings.json").exists());
+        assert!(cache_dir.join("price_2024-02-03.json").exists());
+    }
+}
@@ -2,18 +2,112 @@
  * Configuration definition
  */
 
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use chrono::{FixedOffset, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde_derive::{Deserialize, Serialize};
 
 /// The configuration file schema
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PriceDbConfig {
     /// The full path to the price database file.
     pub price_database_path: String,
     pub alphavantage_api_key: String,
     pub fixerio_api_key: String,
+    /// CoinGecko (demo) API key. Optional.
+    #[serde(default)]
+    pub coingecko_api_key: String,
     /// The full path to the file where the prices will be exported.
     pub prices_path: String,
     pub symbols_path: String,
+    /// Maximum time, in seconds, to wait for another process to release the prices file.
+    #[serde(default = "default_lock_timeout")]
+    pub lock_timeout: u64,
+    /// Currency pairs (COMMODITY/CURRENCY) for which to derive the cross rates from
+    /// the Fixer rates in the `cross_rates_base` currency, i.e. ["AUD/USD"].
+    #[serde(default)]
+    pub cross_rates: Vec<String>,
+    #[serde(default = "default_cross_rates_base")]
+    pub cross_rates_base: String,
+    /// Directory for the cached provider responses. Defaults to `pricedb` in the temp
+    /// directory.
+    #[serde(default)]
+    pub cache_path: String,
+    /// Hours after which the cached responses are downloaded again.
+    #[serde(default = "default_cache_expiry_hours")]
+    pub cache_expiry_hours: u64,
+    /// How the price times are written: `exchange`, `utc`, `date`, or a time zone
+    /// name, i.e. `Europe/Vienna`.
+    #[serde(default)]
+    pub timestamps: TimestampMode,
+    /// Decimal precision of the stored prices, per commodity (ledger symbol) or
+    /// per currency.
+    #[serde(default)]
+    pub precision: PrecisionConfig,
+    /// Yahoo Finance symbol suffixes per namespace (exchange), i.e. `SIX = "SW"`.
+    /// Added to, or overriding, the built-in mapping.
+    #[serde(default)]
+    pub yahoo_namespaces: HashMap<String, String>,
+    /// Vanguard Australia fund (port) IDs per symbol, i.e. `"VANGUARD:HY" = "8106"`.
+    #[serde(default)]
+    pub vanguard_au_funds: HashMap<String, String>,
+    /// CoinGecko coin ids per symbol, i.e. `BTC = "bitcoin"`. Added to, or overriding,
+    /// the built-in mapping.
+    #[serde(default)]
+    pub coingecko_coins: HashMap<String, String>,
+    /// Stooq symbol suffixes per namespace (exchange), i.e. `XETRA = "de"`. Added to,
+    /// or overriding, the built-in mapping.
+    #[serde(default)]
+    pub stooq_namespaces: HashMap<String, String>,
+    /// Hours for which the cached responses are used, per provider (agent), i.e.
+    /// `yahoo_finance = 1`. Defaults to `cache_expiry_hours`.
+    #[serde(default)]
+    pub cache_ttl: HashMap<String, u64>,
+    /// The command for the `exec` agent.
+    #[serde(default)]
+    pub exec: ExecConfig,
+    /// JSON/HTTP providers, by agent name.
+    #[serde(default)]
+    pub json_providers: HashMap<String, JsonProviderConfig>,
+    /// HTML pages to scrape, per symbol, i.e. `[html_pages."VANGUARD:HY"]`.
+    #[serde(default)]
+    pub html_pages: HashMap<String, HtmlPageConfig>,
+    /// The `daemon` schedules.
+    #[serde(default)]
+    pub schedules: Vec<ScheduleConfig>,
+}
+
+impl PriceDbConfig {
+    /// Parses the configured cross-rate pairs into (commodity, currency).
+    pub fn get_cross_rate_pairs(&self) -> Result<Vec<(String, String)>> {
+        self.cross_rates
+            .iter()
+            .map(|pair| match pair.split_once('/') {
+                Some((commodity, currency)) => Ok((
+                    commodity.trim().to_uppercase(),
+                    currency.trim().to_uppercase(),
+                )),
+                None => Err(anyhow!("Invalid cross rate {pair}, expected i.e. AUD/USD")),
+            })
+            .collect()
+    }
+
+    /// Finds the precision setting for a price. The commodity setting takes
+    /// precedence over the setting of the currency the price is in.
+    pub fn get_precision(&self, symbol: &str, currency: &str) -> Option<&Precision> {
+        self.precision
+            .commodities
+            .get(symbol)
+            .or_else(|| self.get_currency_precision(currency))
+    }
+
+    /// Finds the precision setting for amounts in a currency.
+    pub fn get_currency_precision(&self, currency: &str) -> Option<&Precision> {
+        self.precision.currencies.get(currency)
+    }
 }
 
 impl Default for PriceDbConfig {
@@ -22,8 +116,341 @@ impl Default for PriceDbConfig {
             price_database_path: ":memory:".to_owned(),
             alphavantage_api_key: Default::default(),
             fixerio_api_key: Default::default(),
+            coingecko_api_key: Default::default(),
             prices_path: Default::default(),
             symbols_path: Default::default(),
+            lock_timeout: default_lock_timeout(),
+            cross_rates: vec![],
+            cross_rates_base: default_cross_rates_base(),
+            cache_path: Default::default(),
+            cache_expiry_hours: default_cache_expiry_hours(),
+            timestamps: TimestampMode::default(),
+            exec: ExecConfig::default(),
+            json_providers: HashMap::new(),
+            html_pages: HashMap::new(),
+            schedules: vec![],
+            precision: PrecisionConfig::default(),
+            yahoo_namespaces: HashMap::new(),
+            vanguard_au_funds: HashMap::new(),
+            coingecko_coins: HashMap::new(),
+            stooq_namespaces: HashMap::new(),
+            cache_ttl: HashMap::new(),
+        }
+    }
+}
+
+fn default_lock_timeout() -> u64 {
+    30
+}
+
+fn default_cross_rates_base() -> String {
+    "EUR".to_owned()
+}
+
+fn default_cache_expiry_hours() -> u64 {
+    12
+}
+
+/// Scheduled downloads of a group of securities, for the `daemon` mode.
+/// The group is selected with the same filters as in `dl`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScheduleConfig {
+    pub name: String,
+    /// minute hour day-of-month month day-of-week, i.e. "0 16 * * 1-5"
+    pub cron: String,
+    /// The time zone of the cron times, i.e. "Europe/Berlin". The local time, if not set.
+    pub timezone: Option<String>,
+    pub agent: Option<String>,
+    pub currency: Option<String>,
+    pub exchange: Option<String>,
+    pub symbol: Option<String>,
+}
+
+/// External command providing the prices, for the `exec` agent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecConfig {
+    pub command: String,
+    /// The arguments. {symbol}, {namespace}, {currency}, and {date} are replaced with
+    /// the values of the requested price.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Seconds after which the command is stopped.
+    #[serde(default = "default_exec_timeout")]
+    pub timeout: u64,
+}
+
+impl Default for ExecConfig {
+    fn default() -> Self {
+        Self {
+            command: String::default(),
+            args: vec![],
+            timeout: default_exec_timeout(),
         }
     }
 }
+
+fn default_exec_timeout() -> u64 {
+    30
+}
+
+/// A JSON/HTTP provider. See `quote::json_provider`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonProviderConfig {
+    /// The URL template, with {symbol}, {namespace}, {currency}, and {date}.
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// JSON pointer to the value, i.e. `/data/0/price`.
+    pub value: String,
+    /// JSON pointer to the date. Today, if not set.
+    pub date: Option<String>,
+    /// The format of the date, i.e. `%d.%m.%Y`, or `timestamp`.
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    /// JSON pointer to the currency. The requested currency, if not set.
+    pub currency: Option<String>,
+}
+
+fn default_date_format() -> String {
+    "%Y-%m-%d".to_owned()
+}
+
+/// An HTML page with the price of a symbol. See `quote::html_page`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HtmlPageConfig {
+    pub url: String,
+    /// CSS selector of the price element, i.e. `td.nav`.
+    pub price: String,
+    /// CSS selector of the date element. Today, if not set.
+    pub date: Option<String>,
+    /// The format of the date, i.e. `%d %b %Y`.
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    /// "," for the decimal commas.
+    #[serde(default = "default_decimal_separator")]
+    pub decimal_separator: String,
+    /// The currency of the price. The requested currency, if not set.
+    pub currency: Option<String>,
+}
+
+fn default_decimal_separator() -> String {
+    ".".to_owned()
+}
+
+/// The precision settings. A currency can be set in both, i.e. the AUD price in
+/// `commodities`, and the prices quoted in AUD in `currencies`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrecisionConfig {
+    /// Per commodity (ledger symbol), i.e. `VEUR_AS = { decimals = 2, rounding = "half_up" }`.
+    #[serde(default)]
+    pub commodities: HashMap<String, Precision>,
+    /// Per currency of the prices, i.e. `EUR = { decimals = 4 }`.
+    #[serde(default)]
+    pub currencies: HashMap<String, Precision>,
+}
+
+/// Number of decimal places and the rounding applied to a price value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Precision {
+    pub decimals: u32,
+    #[serde(default)]
+    pub rounding: RoundingMode,
+}
+
+impl Precision {
+    pub fn apply(&self, value: Decimal) -> Decimal {
+        value.round_dp_with_strategy(self.decimals, self.rounding.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// Banker's rounding, to the nearest even number on a midpoint.
+    #[default]
+    HalfEven,
+    HalfUp,
+    HalfDown,
+    /// Towards zero (truncate).
+    Down,
+    /// Away from zero.
+    Up,
+    Floor,
+    Ceiling,
+}
+
+impl From<RoundingMode> for RoundingStrategy {
+    fn from(mode: RoundingMode) -> Self {
+        match mode {
+            RoundingMode::HalfEven => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::HalfDown => RoundingStrategy::MidpointTowardZero,
+            RoundingMode::Down => RoundingStrategy::ToZero,
+            RoundingMode::Up => RoundingStrategy::AwayFromZero,
+            RoundingMode::Floor => RoundingStrategy::ToNegativeInfinity,
+            RoundingMode::Ceiling => RoundingStrategy::ToPositiveInfinity,
+        }
+    }
+}
+
+/// The time zone of the price date/time in the price file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TimestampMode {
+    /// The local time at the exchange (or the provider), as received.
+    #[default]
+    Exchange,
+    Utc,
+    /// The local time in the time zone.
+    Zone(Tz),
+    /// Only the date, without the time.
+    Date,
+}
+
+impl TimestampMode {
+    /// Converts the price date/time, received with the given UTC offset.
+    /// The date/time without the offset is only converted to a date.
+    pub fn apply(&self, datetime: NaiveDateTime, offset: Option<FixedOffset>) -> NaiveDateTime {
+        let with_offset = offset.and_then(|offset| offset.from_local_datetime(&datetime).single());
+
+        match (self, with_offset) {
+            (Self::Exchange, _) => datetime,
+            (Self::Utc, Some(datetime)) => datetime.with_timezone(&Utc).naive_local(),
+            (Self::Zone(zone), Some(datetime)) => datetime.with_timezone(zone).naive_local(),
+            (Self::Utc | Self::Zone(_), None) => datetime,
+            (Self::Date, _) => datetime.date().and_time(Default::default()),
+        }
+    }
+}
+
+impl FromStr for TimestampMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "exchange" => Ok(Self::Exchange),
+            "utc" => Ok(Self::Utc),
+            "date" => Ok(Self::Date),
+            _ => s.parse::<Tz>().map(Self::Zone).map_err(|_| {
+                anyhow!("Invalid timestamps {s}, expected exchange, utc, date, or a time zone, i.e. Europe/Vienna")
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for TimestampMode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+impl Display for TimestampMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exchange => write!(f, "exchange"),
+            Self::Utc => write!(f, "utc"),
+            Self::Zone(zone) => write!(f, "{}", zone.name()),
+            Self::Date => write!(f, "date"),
+        }
+    }
+}
+
+impl From<TimestampMode> for String {
+    fn from(mode: TimestampMode) -> Self {
+        mode.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_rounding() {
+        let half_even = Precision {
+            decimals: 2,
+            rounding: RoundingMode::HalfEven,
+        };
+        let half_up = Precision {
+            decimals: 2,
+            rounding: RoundingMode::HalfUp,
+        };
+
+        assert_eq!(dec!(13.24), half_even.apply(dec!(13.245)));
+        assert_eq!(dec!(13.25), half_up.apply(dec!(13.245)));
+        // float noise
+        let noisy = Decimal::from_str_exact("13.2400000000001").unwrap();
+        assert_eq!(dec!(13.24), half_even.apply(noisy));
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let datetime = NaiveDateTime::parse_from_str("2024-01-31 17:35:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let offset = FixedOffset::east_opt(3600);
+        let convert = |mode: &str, offset| mode.parse::<TimestampMode>().unwrap().apply(datetime, offset).to_string();
+
+        assert_eq!("2024-01-31 17:35:00", convert("exchange", offset));
+        assert_eq!("2024-01-31 16:35:00", convert("utc", offset));
+        assert_eq!("2024-02-01 03:35:00", convert("Australia/Sydney", offset));
+        assert_eq!("2024-01-31 00:00:00", convert("date", offset));
+        // unknown offset
+        assert_eq!("2024-01-31 17:35:00", convert("utc", None));
+        assert!("Mars/Olympus".parse::<TimestampMode>().is_err());
+    }
+
+    #[test]
+    fn test_cross_rate_pairs() {
+        let mut cfg = PriceDbConfig {
+            cross_rates: vec!["aud/usd".into(), "GBP / AUD".into()],
+            ..Default::default()
+        };
+
+        let actual = cfg.get_cross_rate_pairs().unwrap();
+
+        assert_eq!(("AUD".to_string(), "USD".to_string()), actual[0]);
+        assert_eq!(("GBP".to_string(), "AUD".to_string()), actual[1]);
+
+        cfg.cross_rates = vec!["AUDUSD".into()];
+        assert!(cfg.get_cross_rate_pairs().is_err());
+    }
+
+    #[test]
+    fn test_precision_lookup() {
+        let mut cfg = PriceDbConfig::default();
+        let precision = |decimals| Precision {
+            decimals,
+            rounding: RoundingMode::Down,
+        };
+        cfg.precision.currencies.insert("EUR".into(), precision(4));
+        cfg.precision.commodities.insert("VEUR_AS".into(), precision(2));
+
+        assert_eq!(2, cfg.get_precision("VEUR_AS", "EUR").unwrap().decimals);
+        assert_eq!(4, cfg.get_precision("EL4X_DE", "EUR").unwrap().decimals);
+        assert!(cfg.get_precision("VTI", "USD").is_none());
+        assert_eq!(4, cfg.get_currency_precision("EUR").unwrap().decimals);
+
+        // AUD as the commodity, and as the currency of the prices
+        cfg.precision.commodities.insert("AUD".into(), precision(6));
+        cfg.precision.currencies.insert("AUD".into(), precision(3));
+        assert_eq!(6, cfg.get_precision("AUD", "EUR").unwrap().decimals);
+        assert_eq!(3, cfg.get_precision("VAS_AX", "AUD").unwrap().decimals);
+        assert_eq!(3, cfg.get_currency_precision("AUD").unwrap().decimals);
+    }
+
+    #[test]
+    fn test_precision_config() {
+        let cfg: PrecisionConfig = serde_json::from_str(
+            r#"{"commodities": {"VEUR_AS": {"decimals": 2}},
+                "currencies": {"EUR": {"decimals": 4, "rounding": "half_up"}}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(2, cfg.commodities["VEUR_AS"].decimals);
+        assert_eq!(RoundingMode::HalfUp, cfg.currencies["EUR"].rounding);
+    }
+}
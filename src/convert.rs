@@ -0,0 +1,226 @@
+/*!
+ * Currency and commodity conversion, using the stored prices.
+ *
+ * Each price is an edge between the commodity and its currency. The prices can be
+ * used in both directions (inverse) and chained, i.e. VHYL_AS -> EUR -> AUD.
+ */
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Display,
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+
+use crate::price_flat_file::PriceRecord;
+
+/// One conversion hop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionStep {
+    pub from: String,
+    pub to: String,
+    /// The rate used, `from` in `to` units.
+    pub rate: Decimal,
+    /// Date/time of the price used.
+    pub datetime: NaiveDateTime,
+    /// The rate is the inverse of the stored price.
+    pub inverse: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversion {
+    pub amount: Decimal,
+    pub from: String,
+    pub to: String,
+    pub result: Decimal,
+    pub steps: Vec<ConversionStep>,
+}
+
+impl Display for Conversion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {} = {} {}", self.amount, self.from, self.result, self.to)?;
+
+        for step in &self.steps {
+            let source = if step.inverse {
+                format!("inverse of {} {} price", step.to, step.from)
+            } else {
+                format!("{} {} price", step.from, step.to)
+            };
+            writeln!(
+                f,
+                "  {} -> {} @ {} ({source} on {})",
+                step.from,
+                step.to,
+                step.rate.round_dp(6).normalize(),
+                step.datetime
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The exchange rates between the commodities, as of a date.
+#[derive(Debug, Default)]
+pub struct RateGraph {
+    edges: HashMap<String, Vec<ConversionStep>>,
+}
+
+impl RateGraph {
+    /// Builds the graph from the price records, using the nearest price on or before
+    /// the given date for each commodity/currency pair.
+    pub fn new(
+        records: impl Iterator<Item = Result<PriceRecord>>,
+        date: NaiveDate,
+    ) -> Result<Self> {
+        // the latest price for each pair
+        let mut prices: HashMap<(String, String), PriceRecord> = HashMap::new();
+
+        for record in records {
+            let record = record?;
+            if record.datetime.date() > date || record.value.is_zero() {
+                continue;
+            }
+
+            let key = (record.symbol.to_owned(), record.currency.to_owned());
+            match prices.get(&key) {
+                Some(existing) if existing.datetime > record.datetime => (),
+                _ => {
+                    prices.insert(key, record);
+                }
+            }
+        }
+
+        let mut graph = Self::default();
+
+        for ((symbol, currency), record) in &prices {
+            graph.add_edge(ConversionStep {
+                from: symbol.to_owned(),
+                to: currency.to_owned(),
+                rate: record.value,
+                datetime: record.datetime,
+                inverse: false,
+            });
+
+            // Use the inverse only when there is no direct price.
+            if !prices.contains_key(&(currency.to_owned(), symbol.to_owned())) {
+                graph.add_edge(ConversionStep {
+                    from: currency.to_owned(),
+                    to: symbol.to_owned(),
+                    rate: Decimal::ONE / record.value,
+                    datetime: record.datetime,
+                    inverse: true,
+                });
+            }
+        }
+
+        Ok(graph)
+    }
+
+    /// Converts the amount, using the path with the fewest hops.
+    pub fn convert(&self, amount: Decimal, from: &str, to: &str) -> Result<Conversion> {
+        let steps = self
+            .find_path(from, to)
+            .ok_or_else(|| anyhow!("No prices found to convert {from} to {to}"))?;
+
+        let result = steps.iter().fold(amount, |value, step| value * step.rate);
+
+        Ok(Conversion {
+            amount,
+            from: from.to_owned(),
+            to: to.to_owned(),
+            result,
+            steps,
+        })
+    }
+
+    fn add_edge(&mut self, step: ConversionStep) {
+        self.edges.entry(step.from.to_owned()).or_default().push(step);
+    }
+
+    /// Breadth-first search for the shortest path.
+    fn find_path(&self, from: &str, to: &str) -> Option<Vec<ConversionStep>> {
+        let mut visited: HashSet<&str> = HashSet::from([from]);
+        let mut queue: VecDeque<(&str, Vec<ConversionStep>)> = VecDeque::from([(from, vec![])]);
+
+        while let Some((node, path)) = queue.pop_front() {
+            if node == to {
+                return Some(path);
+            }
+
+            let Some(edges) = self.edges.get(node) else {
+                continue;
+            };
+
+            // deterministic order, the direct prices first
+            let mut edges: Vec<&ConversionStep> = edges.iter().collect();
+            edges.sort_by(|a, b| (a.inverse, &a.to).cmp(&(b.inverse, &b.to)));
+
+            for edge in edges {
+                if visited.insert(edge.to.as_str()) {
+                    let mut next_path = path.clone();
+                    next_path.push(edge.clone());
+                    queue.push_back((edge.to.as_str(), next_path));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use crate::price_flat_file::PriceReader;
+
+    use super::*;
+
+    const PRICES: &str = "P 2024-01-29 VHYL_AS 59.00 EUR
+P 2024-01-30 VHYL_AS 60.00 EUR
+P 2024-02-01 VHYL_AS 61.00 EUR
+P 2024-01-31 AUD 0.6 EUR
+P 2024-01-31 USD 0.9 EUR
+";
+
+    fn graph(date: &str) -> RateGraph {
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+
+        RateGraph::new(PriceReader::new(PRICES.as_bytes()), date).unwrap()
+    }
+
+    #[test]
+    fn test_direct() {
+        let actual = graph("2024-01-31").convert(dec!(10), "VHYL_AS", "EUR").unwrap();
+
+        assert_eq!(dec!(600), actual.result);
+        assert_eq!(1, actual.steps.len());
+        assert_eq!("2024-01-30", actual.steps[0].datetime.date().to_string());
+    }
+
+    #[test]
+    fn test_inverse() {
+        let actual = graph("2024-01-31").convert(dec!(6), "EUR", "AUD").unwrap();
+
+        assert_eq!(dec!(10), actual.result.round_dp(6));
+        assert!(actual.steps[0].inverse);
+    }
+
+    #[test]
+    fn test_multi_hop() {
+        let actual = graph("2024-01-31").convert(dec!(1250), "VHYL_AS", "AUD").unwrap();
+
+        let path: Vec<&str> = actual.steps.iter().map(|step| step.to.as_str()).collect();
+        assert_eq!(vec!["EUR", "AUD"], path);
+        assert_eq!(dec!(125000), actual.result.round_dp(6));
+    }
+
+    #[test]
+    fn test_no_price_before_date() {
+        let actual = graph("2024-01-28").convert(dec!(1), "VHYL_AS", "EUR");
+
+        assert!(actual.is_err());
+    }
+}
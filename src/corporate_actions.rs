@@ -0,0 +1,175 @@
+/*!
+ * Corporate actions (splits and dividends) store.
+ *
+ * The actions are kept in a CSV file next to the price file, i.e. prices.txt ->
+ * prices.actions.csv, with the columns
+ * date,symbol,action,value,currency
+ * 2024-06-10,NVDA,split,10:1,
+ * 2024-03-15,VHY_AX,dividend,0.85,AUD
+ */
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+
+use crate::model::{CorporateAction, CorporateActionKind};
+
+const HEADER: &str = "date,symbol,action,value,currency";
+
+/// The actions file for the price file.
+pub fn get_actions_path(prices_path: &str) -> PathBuf {
+    Path::new(prices_path).with_extension("actions.csv")
+}
+
+/// Reads the actions. No actions, if the file does not exist.
+pub fn load(file_path: &Path) -> Result<Vec<CorporateAction>> {
+    if !file_path.exists() {
+        return Ok(vec![]);
+    }
+
+    let content = fs::read_to_string(file_path)
+        .with_context(|| format!("Could not read {}", file_path.display()))?;
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && line.trim() != HEADER)
+        .map(|(index, line)| {
+            parse_line(line)
+                .with_context(|| format!("Invalid line {} in {}", index + 1, file_path.display()))
+        })
+        .collect()
+}
+
+/// Saves the actions, ordered by date and symbol.
+pub fn save(file_path: &Path, mut actions: Vec<CorporateAction>) -> Result<()> {
+    actions.sort_by(|a, b| (a.date, &a.symbol).cmp(&(b.date, &b.symbol)));
+
+    let mut output = format!("{HEADER}\n");
+    for action in actions {
+        output += &format!(
+            "{},{},{},{},{}\n",
+            action.date,
+            action.symbol,
+            action.kind.name(),
+            action.kind.value(),
+            action.kind.currency()
+        );
+    }
+
+    fs::write(file_path, output).with_context(|| format!("Could not write {}", file_path.display()))
+}
+
+/// Adds the new actions to the existing ones. One action of a kind per symbol and date
+/// is kept, the new one.
+pub fn merge(existing: Vec<CorporateAction>, new: Vec<CorporateAction>) -> Vec<CorporateAction> {
+    let mut merged = BTreeMap::new();
+
+    for action in existing.into_iter().chain(new) {
+        merged.insert((action.date, action.symbol.to_owned(), action.kind.name()), action);
+    }
+
+    merged.into_values().collect()
+}
+
+fn parse_line(line: &str) -> Result<CorporateAction> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let [date, symbol, action, value, currency] = fields[..] else {
+        return Err(anyhow!("Expected {HEADER}"));
+    };
+
+    let kind = match action {
+        "split" => {
+            let (numerator, denominator) = value
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Invalid split ratio {value}"))?;
+            CorporateActionKind::Split {
+                numerator: Decimal::from_str_exact(numerator)?,
+                denominator: Decimal::from_str_exact(denominator)?,
+            }
+        }
+        "dividend" => CorporateActionKind::Dividend {
+            amount: Decimal::from_str_exact(value)?,
+            currency: currency.to_owned(),
+        },
+        _ => return Err(anyhow!("Unknown action {action}")),
+    };
+
+    Ok(CorporateAction {
+        symbol: symbol.to_owned(),
+        date: NaiveDate::parse_from_str(date, "%Y-%m-%d")?,
+        kind,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+    }
+
+    fn split() -> CorporateAction {
+        CorporateAction {
+            symbol: "NVDA".into(),
+            date: date("2024-06-10"),
+            kind: CorporateActionKind::Split {
+                numerator: dec!(10),
+                denominator: dec!(1),
+            },
+        }
+    }
+
+    fn dividend(amount: Decimal) -> CorporateAction {
+        CorporateAction {
+            symbol: "VHY_AX".into(),
+            date: date("2024-03-15"),
+            kind: CorporateActionKind::Dividend {
+                amount,
+                currency: "AUD".into(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_actions_path() {
+        assert_eq!(PathBuf::from("/data/prices.actions.csv"), get_actions_path("/data/prices.txt"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prices.actions.csv");
+        assert!(load(&path).unwrap().is_empty());
+
+        save(&path, vec![split(), dividend(dec!(0.8512))]).unwrap();
+
+        assert_eq!(
+            "date,symbol,action,value,currency\n2024-03-15,VHY_AX,dividend,0.8512,AUD\n2024-06-10,NVDA,split,10:1,\n",
+            fs::read_to_string(&path).unwrap()
+        );
+        assert_eq!(vec![dividend(dec!(0.8512)), split()], load(&path).unwrap());
+    }
+
+    #[test]
+    fn test_merge_replaces() {
+        let actual = merge(vec![dividend(dec!(0.85)), split()], vec![dividend(dec!(0.8512))]);
+
+        assert_eq!(vec![dividend(dec!(0.8512)), split()], actual);
+    }
+
+    #[test]
+    fn test_invalid_line() {
+        assert!(parse_line("2024-06-10,NVDA,merger,,").is_err());
+        assert!(parse_line("2024-06-10,NVDA,split,10,").is_err());
+        assert!(parse_line("2024-06-10,NVDA").is_err());
+    }
+}
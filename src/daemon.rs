@@ -0,0 +1,488 @@
+/*!
+ * Scheduled price downloads, for the `daemon` mode.
+ *
+ * Each schedule in the `[[schedules]]` configuration section downloads the prices of
+ * a group of securities (by agent, currency, exchange, or symbol) at the times given
+ * by a cron expression: minute hour day-of-month month day-of-week, i.e.
+ * "0 16 * * 1-5" for 16:00 on the business days.
+ * The fields accept `*`, numbers, ranges (1-5), lists (1,3,5), and steps (0-59/15).
+ * The day of the week is 0-7, where both 0 and 7 are Sunday.
+ *
+ * The time of the last run of each schedule is kept in a JSON file next to the price
+ * file, so that the runs missed while the daemon was not running are caught up.
+ */
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use serde_json::{json, Map, Value};
+
+use crate::{config::ScheduleConfig, model::SecurityFilter};
+
+/// The schedules are checked up to this many days ahead.
+const MAX_DAYS_AHEAD: u64 = 5 * 366;
+
+/// A parsed cron expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    minutes: BTreeSet<u32>,
+    hours: BTreeSet<u32>,
+    days: BTreeSet<u32>,
+    months: BTreeSet<u32>,
+    /// 0 = Sunday
+    weekdays: BTreeSet<u32>,
+    /// Day of the month and day of the week are restricted (not *).
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    /// The first scheduled time after the given one.
+    pub fn next_after<Z: TimeZone>(&self, after: &DateTime<Z>) -> Option<DateTime<Z>> {
+        let timezone = after.timezone();
+        let local = after.naive_local();
+        let start_date = local.date();
+        // the next minute
+        let (start_hour, start_minute) = (local.hour(), local.minute() + 1);
+
+        for date in start_date.iter_days().take(MAX_DAYS_AHEAD as usize) {
+            if !self.matches_date(date) {
+                continue;
+            }
+
+            for &hour in &self.hours {
+                if date == start_date && hour < start_hour {
+                    continue;
+                }
+                for &minute in &self.minutes {
+                    if date == start_date && hour == start_hour && minute < start_minute {
+                        continue;
+                    }
+
+                    let naive = date.and_hms_opt(hour, minute, 0)?;
+                    // Skips the times in the DST gap.
+                    if let Some(datetime) = timezone.from_local_datetime(&naive).earliest() {
+                        if datetime > *after {
+                            return Some(datetime);
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if !self.months.contains(&date.month()) {
+            return false;
+        }
+
+        let day = self.days.contains(&date.day());
+        let weekday = self.weekdays.contains(&date.weekday().num_days_from_sunday());
+
+        // As in cron, either of the days matches when both are restricted.
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(expression: &str) -> Result<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(anyhow!(
+                "Invalid cron expression {expression:?}. Expected: minute hour day-of-month month day-of-week"
+            ));
+        };
+
+        let mut weekdays_set = parse_field(weekdays, 0, 7)?;
+        if weekdays_set.remove(&7) {
+            weekdays_set.insert(0);
+        }
+
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekdays_set,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+        })
+    }
+}
+
+/// Parses a cron field, i.e. *, */15, 1-5, 0,30, or 8-18/2, into the values.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<BTreeSet<u32>> {
+    let mut values = BTreeSet::new();
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0)),
+            None => (part, Some(1)),
+        };
+        let step = step.ok_or_else(|| anyhow!("Invalid step in {field:?}"))?;
+
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((from, to)) => (from.parse()?, to.parse()?),
+                None => {
+                    let value = range.parse()?;
+                    // 5/10 is from 5 to the end
+                    if part.contains('/') {
+                        (value, max)
+                    } else {
+                        (value, value)
+                    }
+                }
+            },
+        };
+        if from < min || to > max || from > to {
+            return Err(anyhow!("{field:?} is out of the range {min}-{max}"));
+        }
+
+        values.extend((from..=to).step_by(step as usize));
+    }
+
+    Ok(values)
+}
+
+/// A configured schedule, with the parsed cron expression and time zone.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub name: String,
+    cron: CronSchedule,
+    /// The local time zone, if not set.
+    timezone: Option<Tz>,
+    pub filter: SecurityFilter,
+}
+
+impl Schedule {
+    pub fn new(config: &ScheduleConfig) -> Result<Self> {
+        let cron = config
+            .cron
+            .parse()
+            .with_context(|| format!("Invalid schedule {}", config.name))?;
+        let timezone = match &config.timezone {
+            Some(timezone) => Some(
+                timezone
+                    .parse::<Tz>()
+                    .map_err(|e| anyhow!("Invalid time zone in the schedule {}: {e}", config.name))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            name: config.name.to_owned(),
+            cron,
+            timezone,
+            filter: SecurityFilter {
+                currency: config.currency.clone(),
+                agent: config.agent.clone(),
+                exchange: config.exchange.clone(),
+                symbol: config.symbol.clone(),
+            },
+        })
+    }
+
+    /// The next run after the given time.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self.timezone {
+            Some(timezone) => self
+                .cron
+                .next_after(&after.with_timezone(&timezone))
+                .map(|next| next.with_timezone(&Utc)),
+            None => self
+                .cron
+                .next_after(&after.with_timezone(&Local))
+                .map(|next| next.with_timezone(&Utc)),
+        }
+    }
+
+    /// The next run, after the last run, or after the daemon start if the schedule has
+    /// not run yet. A run missed since the last run is due immediately (in the past).
+    pub fn next_run(&self, state: &DaemonState, started: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let after = state.last_run(&self.name).unwrap_or(started);
+
+        self.next_after(after)
+    }
+}
+
+/// Parses the configured schedules. The names are the keys in the state file, so they
+/// must be set and unique.
+pub fn parse_schedules(configs: &[ScheduleConfig]) -> Result<Vec<Schedule>> {
+    let mut names = BTreeSet::new();
+    for config in configs {
+        if config.name.trim().is_empty() {
+            return Err(anyhow!("A schedule without a name ({})", config.cron));
+        }
+        if !names.insert(config.name.as_str()) {
+            return Err(anyhow!("Duplicate schedule name {}", config.name));
+        }
+    }
+
+    configs.iter().map(Schedule::new).collect()
+}
+
+/// The earliest next run of the schedules.
+pub fn next_due(schedules: &[Schedule], state: &DaemonState, started: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    schedules
+        .iter()
+        .filter_map(|schedule| schedule.next_run(state, started))
+        .min()
+}
+
+/// The schedules to run now.
+pub fn due_schedules<'a>(
+    schedules: &'a [Schedule],
+    state: &DaemonState,
+    started: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Vec<&'a Schedule> {
+    schedules
+        .iter()
+        .filter(|schedule| schedule.next_run(state, started).is_some_and(|next| next <= now))
+        .collect()
+}
+
+/// The last run of each schedule.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DaemonState {
+    runs: BTreeMap<String, RunState>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunState {
+    pub last_run: DateTime<Utc>,
+    /// "ok", or the error
+    pub result: String,
+}
+
+impl DaemonState {
+    /// The state file for the price file.
+    pub fn get_path(prices_path: &str) -> PathBuf {
+        Path::new(prices_path).with_extension("daemon.json")
+    }
+
+    /// Reads the state. Empty, if the file does not exist.
+    pub fn load(file_path: &Path) -> Result<Self> {
+        if !file_path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(file_path)?;
+        let value: Value = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid state file {}", file_path.display()))?;
+
+        let mut runs = BTreeMap::new();
+        for (name, run) in value.as_object().into_iter().flatten() {
+            let last_run = run["last_run"]
+                .as_str()
+                .ok_or_else(|| anyhow!("No last_run for {name} in {}", file_path.display()))?;
+            runs.insert(
+                name.to_owned(),
+                RunState {
+                    last_run: DateTime::parse_from_rfc3339(last_run)?.with_timezone(&Utc),
+                    result: run["result"].as_str().unwrap_or_default().to_owned(),
+                },
+            );
+        }
+
+        Ok(Self { runs })
+    }
+
+    pub fn save(&self, file_path: &Path) -> Result<()> {
+        let runs: Map<String, Value> = self
+            .runs
+            .iter()
+            .map(|(name, run)| {
+                let value = json!({
+                    "last_run": run.last_run.to_rfc3339(),
+                    "result": run.result,
+                });
+                (name.to_owned(), value)
+            })
+            .collect();
+
+        fs::write(file_path, format!("{:#}\n", Value::Object(runs)))
+            .with_context(|| format!("Could not write {}", file_path.display()))
+    }
+
+    pub fn last_run(&self, name: &str) -> Option<DateTime<Utc>> {
+        self.runs.get(name).map(|run| run.last_run)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RunState> {
+        self.runs.get(name)
+    }
+
+    pub fn record(&mut self, name: &str, last_run: DateTime<Utc>, result: &Result<()>) {
+        let result = match result {
+            Ok(()) => "ok".to_owned(),
+            Err(error) => format!("{error:#}"),
+        };
+
+        self.runs.insert(name.to_owned(), RunState { last_run, result });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+
+    fn cron(expression: &str) -> CronSchedule {
+        expression.parse().unwrap()
+    }
+
+    fn berlin(text: &str) -> DateTime<Tz> {
+        let naive = chrono::NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+        Berlin.from_local_datetime(&naive).unwrap()
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        assert_eq!(BTreeSet::from([0, 15, 30, 45]), parse_field("*/15", 0, 59).unwrap());
+        assert_eq!(BTreeSet::from([1, 2, 3, 4, 5]), parse_field("1-5", 0, 7).unwrap());
+        assert_eq!(BTreeSet::from([8, 10, 12, 30]), parse_field("8-12/2,30", 0, 59).unwrap());
+        assert_eq!(BTreeSet::from([50, 55]), parse_field("50/5", 0, 59).unwrap());
+        assert!(parse_field("60", 0, 59).is_err());
+        assert!(parse_field("5-1", 0, 59).is_err());
+        assert!(parse_field("*/0", 0, 59).is_err());
+        assert!("0 16 * *".parse::<CronSchedule>().is_err());
+    }
+
+    #[test]
+    fn test_business_days() {
+        let schedule = cron("0 16 * * 1-5");
+
+        // Friday 2024-02-02, before and after 16:00
+        assert_eq!(
+            berlin("2024-02-02 16:00"),
+            schedule.next_after(&berlin("2024-02-02 09:30")).unwrap()
+        );
+        assert_eq!(
+            berlin("2024-02-05 16:00"),
+            schedule.next_after(&berlin("2024-02-02 16:00")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_days_of_month_or_week() {
+        // the 1st, or any Sunday (7)
+        let schedule = cron("30 6 1 * 7");
+
+        assert_eq!(
+            berlin("2024-02-01 06:30"),
+            schedule.next_after(&berlin("2024-01-28 07:00")).unwrap()
+        );
+        assert_eq!(
+            berlin("2024-02-04 06:30"),
+            schedule.next_after(&berlin("2024-02-01 06:30")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_dst_gap() {
+        // 02:30 does not exist on 2024-03-31 in Berlin
+        let schedule = cron("30 2 * * *");
+
+        assert_eq!(
+            berlin("2024-04-01 02:30"),
+            schedule.next_after(&berlin("2024-03-30 03:00")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_catch_up_missed_run() {
+        let config = ScheduleConfig {
+            name: "fx".into(),
+            cron: "0 16 * * *".into(),
+            timezone: Some("Europe/Berlin".into()),
+            ..Default::default()
+        };
+        let schedule = Schedule::new(&config).unwrap();
+        let now = berlin("2024-02-03 10:00").with_timezone(&Utc);
+
+        let mut state = DaemonState::default();
+        assert_eq!(Some(berlin("2024-02-03 16:00").with_timezone(&Utc)), schedule.next_run(&state, now));
+
+        // the run on the 2nd was missed
+        state.record("fx", berlin("2024-02-01 16:00").with_timezone(&Utc), &Ok(()));
+        assert_eq!(Some(berlin("2024-02-02 16:00").with_timezone(&Utc)), schedule.next_run(&state, now));
+    }
+
+    #[test]
+    fn test_first_run_without_state() {
+        let configs = [ScheduleConfig {
+            name: "fx".into(),
+            cron: "0 16 * * *".into(),
+            timezone: Some("Europe/Berlin".into()),
+            ..Default::default()
+        }];
+        let schedules = parse_schedules(&configs).unwrap();
+        let started = berlin("2024-02-03 10:00").with_timezone(&Utc);
+        let at = |text| berlin(text).with_timezone(&Utc);
+        let mut state = DaemonState::default();
+
+        // waiting, across the wake-ups before the scheduled time
+        assert_eq!(Some(at("2024-02-03 16:00")), next_due(&schedules, &state, started));
+        assert!(due_schedules(&schedules, &state, started, at("2024-02-03 11:00")).is_empty());
+        assert!(due_schedules(&schedules, &state, started, at("2024-02-03 15:59")).is_empty());
+
+        // due, once the scheduled time has passed
+        let now = at("2024-02-03 16:00") + chrono::Duration::seconds(2);
+        let due = due_schedules(&schedules, &state, started, now);
+        assert_eq!(1, due.len());
+        assert_eq!("fx", due[0].name);
+
+        // after the run, the next one is on the next day
+        state.record("fx", now, &Ok(()));
+        assert!(due_schedules(&schedules, &state, started, now).is_empty());
+        assert_eq!(Some(at("2024-02-04 16:00")), next_due(&schedules, &state, started));
+    }
+
+    #[test]
+    fn test_schedule_names() {
+        let config = |name: &str| ScheduleConfig {
+            name: name.into(),
+            cron: "0 16 * * *".into(),
+            ..Default::default()
+        };
+
+        assert_eq!(2, parse_schedules(&[config("fx"), config("funds")]).unwrap().len());
+        assert!(parse_schedules(&[config("fx"), config("fx")]).is_err());
+        assert!(parse_schedules(&[config(" ")]).is_err());
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = DaemonState::get_path(&dir.path().join("prices.txt").to_string_lossy());
+        assert!(path.ends_with("prices.daemon.json"));
+        assert_eq!(DaemonState::default(), DaemonState::load(&path).unwrap());
+
+        let mut state = DaemonState::default();
+        let time = berlin("2024-02-02 16:00").with_timezone(&Utc);
+        state.record("fx", time, &Ok(()));
+        state.record("funds", time, &Err(anyhow!("No price")));
+        state.save(&path).unwrap();
+
+        let actual = DaemonState::load(&path).unwrap();
+        assert_eq!(state, actual);
+        assert_eq!("No price", actual.get("funds").unwrap().result);
+    }
+}
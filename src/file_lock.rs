@@ -0,0 +1,168 @@
+/*!
+ * Advisory locking for the prices file.
+ *
+ * Concurrent runs (i.e. separate cron entries for different agents) perform a
+ * load -> modify -> save cycle on the same file. The lock serializes these cycles
+ * so that the last writer does not discard the other's prices.
+ *
+ * The lock is held on a sidecar file (`prices.txt.lock`) so that the prices file
+ * itself can still be rewritten while locked, also on Windows.
+ */
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+
+/// How often to retry while waiting for the lock.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A lock on a file, exclusive for writing or shared for reading. Released when dropped.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl FileLock {
+    /// Acquires an exclusive lock for the given file, waiting at most `timeout`.
+    pub fn acquire(file_path: &str, timeout: Duration) -> Result<Self> {
+        Self::wait(file_path, timeout, false)
+    }
+
+    /// Acquires a shared lock for reading the given file, waiting at most `timeout`.
+    /// Several readers can hold it at once, but not while a writer holds the exclusive lock.
+    pub fn acquire_shared(file_path: &str, timeout: Duration) -> Result<Self> {
+        Self::wait(file_path, timeout, true)
+    }
+
+    /// Acquires an exclusive lock without blocking the async runtime while waiting.
+    pub async fn acquire_async(file_path: &str, timeout: Duration) -> Result<Self> {
+        let (file, path) = open_lock_file(file_path)?;
+
+        let started = Instant::now();
+        while !try_lock(&file, &path, false)? {
+            check_timeout(file_path, started, timeout)?;
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+
+        log::debug!("acquired lock {}", path.display());
+
+        Ok(Self { file, path })
+    }
+
+    fn wait(file_path: &str, timeout: Duration, shared: bool) -> Result<Self> {
+        let (file, path) = open_lock_file(file_path)?;
+
+        let started = Instant::now();
+        while !try_lock(&file, &path, shared)? {
+            check_timeout(file_path, started, timeout)?;
+            thread::sleep(RETRY_INTERVAL);
+        }
+
+        log::debug!("acquired lock {}", path.display());
+
+        Ok(Self { file, path })
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.file.unlock() {
+            log::warn!("Could not release the lock {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+fn open_lock_file(file_path: &str) -> Result<(File, PathBuf)> {
+    let path = get_lock_path(file_path);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Could not open the lock file {}", path.display()))?;
+
+    Ok((file, path))
+}
+
+/// Tries to lock the file. False if another process holds the lock.
+fn try_lock(file: &File, path: &Path, shared: bool) -> Result<bool> {
+    let result = if shared {
+        file.try_lock_shared()
+    } else {
+        file.try_lock()
+    };
+
+    match result {
+        Ok(()) => Ok(true),
+        Err(TryLockError::WouldBlock) => {
+            log::debug!("waiting for the lock on {}", path.display());
+            Ok(false)
+        }
+        Err(TryLockError::Error(e)) => {
+            Err(e).with_context(|| format!("Could not lock {}", path.display()))
+        }
+    }
+}
+
+fn check_timeout(file_path: &str, started: Instant, timeout: Duration) -> Result<()> {
+    if started.elapsed() >= timeout {
+        return Err(anyhow!(
+            "Timed out after {}s waiting for the lock on {}. Another pricedb process may be updating the file.",
+            timeout.as_secs(),
+            file_path
+        ));
+    }
+    Ok(())
+}
+
+/// The lock file lives next to the locked file.
+fn get_lock_path(file_path: &str) -> PathBuf {
+    PathBuf::from(format!("{file_path}.lock"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::FileLock;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prices.txt");
+        let path = path.to_str().unwrap();
+
+        let first = FileLock::acquire(path, Duration::from_secs(1)).expect("first lock");
+
+        let second = FileLock::acquire(path, Duration::from_millis(200));
+        let error = second.expect_err("the lock is taken");
+        assert!(error.to_string().starts_with("Timed out"));
+
+        // released on drop
+        drop(first);
+        assert!(FileLock::acquire(path, Duration::from_secs(1)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_shared_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prices.txt");
+        let path = path.to_str().unwrap();
+
+        // readers do not block each other
+        let reader = FileLock::acquire_shared(path, Duration::from_secs(1)).expect("first reader");
+        assert!(FileLock::acquire_shared(path, Duration::from_secs(1)).is_ok());
+
+        // but block the writer
+        let writer = FileLock::acquire_async(path, Duration::from_millis(200)).await;
+        assert!(writer.is_err());
+
+        drop(reader);
+        assert!(FileLock::acquire_async(path, Duration::from_secs(1)).await.is_ok());
+    }
+}
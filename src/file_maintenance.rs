@@ -0,0 +1,243 @@
+/*!
+ * Price file maintenance.
+ * Merging, splitting, and deduplicating price files.
+ *
+ * All the operations work on the complete price history, unlike `PriceFlatFile`,
+ * which updates only the latest price per symbol.
+ */
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, NaiveDate};
+
+use crate::price_flat_file::{load_records, PriceRecord};
+
+/// How to choose between two prices for the same symbol and currency on the same date.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergeStrategy {
+    /// The price with the later time wins.
+    Newest,
+    /// Prices from the given file win.
+    Source(String),
+}
+
+/// How to split a price file.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum SplitBy {
+    Year,
+    Commodity,
+}
+
+/// Combines the records from several files.
+/// Only one price per symbol, currency, and date is kept, as chosen by the strategy.
+/// On a tie, the file listed later wins.
+pub fn merge(file_paths: &[String], strategy: &MergeStrategy) -> Result<Vec<PriceRecord>> {
+    let mut merged: HashMap<(String, String, NaiveDate), (PriceRecord, bool)> = HashMap::new();
+
+    // compare the actual files, i.e. ./prices.txt and prices.txt are the same
+    let canonical_paths = file_paths
+        .iter()
+        .map(|path| canonicalize(path))
+        .collect::<Result<Vec<PathBuf>>>()?;
+    let preferred_path = match strategy {
+        MergeStrategy::Source(source) => {
+            let source_path = canonicalize(source)?;
+            if !canonical_paths.contains(&source_path) {
+                return Err(anyhow!("The preferred file {source} is not one of the merged files"));
+            }
+            Some(source_path)
+        }
+        MergeStrategy::Newest => None,
+    };
+
+    for (file_path, canonical_path) in file_paths.iter().zip(&canonical_paths) {
+        let preferred = preferred_path.as_ref() == Some(canonical_path);
+
+        for record in load_records(file_path)? {
+            let key = (
+                record.symbol.to_owned(),
+                record.currency.to_owned(),
+                record.datetime.date(),
+            );
+
+            let replace = match merged.get(&key) {
+                None => true,
+                Some((existing, existing_preferred)) => {
+                    if preferred != *existing_preferred {
+                        preferred
+                    } else {
+                        record.datetime >= existing.datetime
+                    }
+                }
+            };
+
+            if replace {
+                merged.insert(key, (record, preferred));
+            }
+        }
+    }
+
+    Ok(merged.into_values().map(|(record, _)| record).collect())
+}
+
+fn canonicalize(file_path: &str) -> Result<PathBuf> {
+    fs::canonicalize(file_path).with_context(|| format!("Could not find the price file {file_path}"))
+}
+
+/// Removes repeated (symbol, currency, date/time) entries. The last occurrence is kept.
+pub fn dedupe(records: Vec<PriceRecord>) -> Vec<PriceRecord> {
+    let mut unique = HashMap::new();
+
+    for record in records {
+        let key = (record.symbol.to_owned(), record.currency.to_owned(), record.datetime);
+        unique.insert(key, record);
+    }
+
+    unique.into_values().collect()
+}
+
+/// Groups the records by year or by commodity.
+/// The keys are used as the file name suffixes.
+pub fn split(records: Vec<PriceRecord>, by: SplitBy) -> BTreeMap<String, Vec<PriceRecord>> {
+    let mut groups: BTreeMap<String, Vec<PriceRecord>> = BTreeMap::new();
+
+    for record in records {
+        let key = match by {
+            SplitBy::Year => record.datetime.year().to_string(),
+            SplitBy::Commodity => record.symbol.to_owned(),
+        };
+
+        groups.entry(key).or_default().push(record);
+    }
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::NaiveDateTime;
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    fn record(date_time: &str, symbol: &str, value: &str) -> PriceRecord {
+        PriceRecord {
+            datetime: NaiveDateTime::parse_from_str(date_time, "%Y-%m-%d %H:%M:%S").unwrap(),
+            symbol: symbol.into(),
+            value: Decimal::from_str_exact(value).unwrap(),
+            currency: "EUR".into(),
+        }
+    }
+
+    fn write_files(dir: &tempfile::TempDir) -> (String, String) {
+        let first = dir.path().join("first.txt");
+        let second = dir.path().join("second.txt");
+
+        fs::write(
+            &first,
+            "P 2023-04-14 GBP 1.132283 EUR\nP 2023-04-15 12:00:00 VEUR_AS 1.5 EUR\n",
+        )
+        .unwrap();
+        fs::write(
+            &second,
+            "P 2023-04-14 GBP 1.14 EUR\nP 2023-04-15 10:00:00 VEUR_AS 1.4 EUR\nP 2023-03-11 USD 1.11 EUR\n",
+        )
+        .unwrap();
+
+        (
+            first.to_str().unwrap().to_owned(),
+            second.to_str().unwrap().to_owned(),
+        )
+    }
+
+    #[test]
+    fn test_merge_newest() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = write_files(&dir);
+
+        let mut actual = merge(&[first, second], &MergeStrategy::Newest).unwrap();
+        crate::price_flat_file::sort_records(&mut actual);
+
+        assert_eq!(3, actual.len());
+        // same date/time, later file wins
+        assert_eq!(record("2023-04-14 00:00:00", "GBP", "1.14"), actual[1]);
+        // later time wins
+        assert_eq!(record("2023-04-15 12:00:00", "VEUR_AS", "1.5"), actual[2]);
+    }
+
+    #[test]
+    fn test_merge_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = write_files(&dir);
+
+        // the same file, by another path
+        let second_path = dir.path().join(".").join("second.txt");
+        let strategy = MergeStrategy::Source(second_path.to_str().unwrap().to_owned());
+        let mut actual = merge(&[first.to_owned(), second], &strategy).unwrap();
+        crate::price_flat_file::sort_records(&mut actual);
+
+        assert_eq!(3, actual.len());
+        assert_eq!(record("2023-04-15 10:00:00", "VEUR_AS", "1.4"), actual[2]);
+
+        // not one of the inputs
+        let strategy = MergeStrategy::Source("other.txt".into());
+        assert!(merge(&[first], &strategy).is_err());
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let records = vec![
+            record("2023-04-14 00:00:00", "GBP", "1.13"),
+            record("2023-04-14 00:00:00", "GBP", "1.14"),
+            record("2023-04-15 00:00:00", "GBP", "1.15"),
+        ];
+
+        let actual = dedupe(records);
+
+        assert_eq!(2, actual.len());
+        assert!(actual.contains(&record("2023-04-14 00:00:00", "GBP", "1.14")));
+    }
+
+    #[test]
+    fn test_currencies_kept() {
+        let eur = record("2024-01-31 00:00:00", "BTC", "39000");
+        let usd = PriceRecord {
+            currency: "USD".into(),
+            ..record("2024-01-31 00:00:00", "BTC", "42500")
+        };
+
+        let actual = dedupe(vec![eur.clone(), usd.clone()]);
+        assert_eq!(2, actual.len());
+
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.txt");
+        let second = dir.path().join("second.txt");
+        fs::write(&first, "P 2024-01-31 BTC 39000 EUR\n").unwrap();
+        fs::write(&second, "P 2024-01-31 BTC 42500 USD\n").unwrap();
+        let files = [first.to_str().unwrap().to_owned(), second.to_str().unwrap().to_owned()];
+
+        let mut actual = merge(&files, &MergeStrategy::Newest).unwrap();
+        crate::price_flat_file::sort_records(&mut actual);
+        assert_eq!(vec![eur, usd], actual);
+    }
+
+    #[test]
+    fn test_split_by_year() {
+        let records = vec![
+            record("2022-12-31 00:00:00", "GBP", "1.13"),
+            record("2023-01-02 00:00:00", "GBP", "1.14"),
+            record("2023-01-02 00:00:00", "USD", "0.9"),
+        ];
+
+        let actual = split(records, SplitBy::Year);
+
+        assert_eq!(vec!["2022", "2023"], actual.keys().collect::<Vec<_>>());
+        assert_eq!(2, actual["2023"].len());
+    }
+}
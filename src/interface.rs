@@ -1,6 +1,9 @@
 /*!
  * CLI interface
  */
+use chrono::NaiveDate;
+use pricedb::{file_maintenance::SplitBy, report::OutputFormat};
+
 #[derive(clap::Parser, Debug)]
 #[command(name = "Price Database")]
 #[command(author, version, about, long_about = None)]  // these are loaded from Cargo.toml
@@ -38,11 +41,208 @@ pub(crate) enum Commands {
         exchange: Option<String>,
         #[arg(short, long)]
         symbol: Option<String>,
-}
+        /// Do not use or update the cached responses
+        #[arg(long)]
+        no_cache: bool,
+        /// Download again, ignoring the cached responses
+        #[arg(long)]
+        refresh: bool,
+        /// Download the historical prices on this date (YYYY-MM-DD) and add them to
+        /// the price history. Not supported by all the agents.
+        #[arg(short, long)]
+        date: Option<NaiveDate>,
+    },
+
+    #[command(about = "Add a price manually, i.e. for assets without a price provider")]
+    Add {
+        /// Ledger symbol, or NAMESPACE:SYMBOL, as in the symbols file
+        symbol: String,
+        value: String,
+        currency: String,
+        /// Date of the price (YYYY-MM-DD). Defaults to today.
+        #[arg(short, long)]
+        date: Option<String>,
+        /// Time of the price (HH:MM:SS)
+        #[arg(short, long)]
+        time: Option<String>,
+        /// Replace the stored price also when it is newer
+        #[arg(long)]
+        force: bool,
+        #[arg(short, long)]
+        price_file: Option<String>,
+        #[arg(short = 'f', long)]
+        symbols_file: Option<String>,
+    },
+
+    #[command(about = "Show the stored prices")]
+    #[command(visible_alias = "list")]
+    Show {
+        /// Ledger symbol of the commodity
+        commodity: Option<String>,
+        /// Date of the price (YYYY-MM-DD)
+        #[arg(short, long)]
+        date: Option<String>,
+        /// Time of the price (HH:MM:SS)
+        #[arg(short, long)]
+        time: Option<String>,
+        /// Start of the date range (YYYY-MM-DD)
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// End of the date range (YYYY-MM-DD)
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        /// Show all the prices, not only the latest one for each symbol
+        #[arg(long)]
+        history: bool,
+        #[arg(short, long, value_enum, default_value = "table")]
+        output: OutputFormat,
+        #[arg(short, long)]
+        price_file: Option<String>,
+        #[arg(short = 'f', long)]
+        symbols_file: Option<String>,
+        // Symbol filters
+        #[arg(short, long)]
+        currency: Option<String>,
+        #[arg(short, long)]
+        agent: Option<String>,
+        #[arg(short = 'x', long)]
+        exchange: Option<String>,
+        #[arg(short, long)]
+        symbol: Option<String>,
+    },
+
+    #[command(about = "Convert an amount between commodities, using the stored prices")]
+    Convert {
+        amount: String,
+        from: String,
+        to: String,
+        /// Use the prices on or before this date (YYYY-MM-DD). Defaults to today.
+        #[arg(short, long)]
+        date: Option<NaiveDate>,
+        #[arg(short, long)]
+        price_file: Option<String>,
+    },
+
+    #[command(about = "Price file maintenance")]
+    #[command(arg_required_else_help(true))]
+    #[clap(subcommand)]
+    File(FileCmd),
+
+    #[command(about = "Cached provider responses")]
+    #[command(arg_required_else_help(true))]
+    #[clap(subcommand)]
+    Cache(CacheCmd),
+
+    #[command(about = "Download the prices on the configured schedules, until stopped")]
+    Daemon {
+        /// Show the schedules with their last and next runs, and exit
+        #[arg(long)]
+        list: bool,
+    },
+
+    #[command(about = "Serve the stored prices over a local HTTP/JSON API")]
+    Serve {
+        /// The address to listen on. Only local connections by default.
+        #[arg(short, long, default_value = "127.0.0.1")]
+        bind: String,
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        #[arg(short, long)]
+        price_file: Option<String>,
+        #[arg(short = 'f', long)]
+        symbols_file: Option<String>,
+    },
+
+    #[command(about = "Corporate actions (splits and dividends)")]
+    #[command(arg_required_else_help(true))]
+    #[clap(subcommand)]
+    Actions(ActionsCmd),
 }
 
 #[derive(clap::Subcommand, Debug)]
 pub(super) enum ConfigCmd {
     /// Displays the current configuration
     Show,
-}
\ No newline at end of file
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub(super) enum FileCmd {
+    /// Merges several price files into one
+    Merge {
+        #[arg(required = true)]
+        files: Vec<String>,
+        #[arg(short, long)]
+        output: String,
+        /// On conflict, prefer the prices from this file instead of the newest ones
+        #[arg(long)]
+        prefer: Option<String>,
+    },
+    /// Splits a price file into one file per year or per commodity
+    Split {
+        file: String,
+        #[arg(short, long, value_enum, default_value = "year")]
+        by: SplitBy,
+        #[arg(short, long)]
+        output_dir: Option<String>,
+    },
+    /// Removes repeated (symbol, date/time) entries
+    Dedupe {
+        file: String,
+        /// Write into this file instead of rewriting the original
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub(super) enum CacheCmd {
+    /// Lists the cached responses
+    List,
+    /// Deletes the cached responses
+    Clear {
+        /// Delete only the expired responses
+        #[arg(long)]
+        expired: bool,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub(super) enum ActionsCmd {
+    /// Downloads the splits and dividends and adds them to the actions file
+    Dl {
+        /// Start of the date range (YYYY-MM-DD). Defaults to a year ago.
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// End of the date range (YYYY-MM-DD). Defaults to today.
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        #[arg(short, long)]
+        price_file: Option<String>,
+        #[arg(short = 'f', long)]
+        symbols_file: Option<String>,
+        // Symbol filters
+        #[arg(short, long)]
+        currency: Option<String>,
+        #[arg(short, long)]
+        agent: Option<String>,
+        #[arg(short = 'x', long)]
+        exchange: Option<String>,
+        #[arg(short, long)]
+        symbol: Option<String>,
+    },
+    /// Lists the stored splits and dividends
+    Show {
+        /// Ledger symbol of the commodity
+        commodity: Option<String>,
+        /// Start of the date range (YYYY-MM-DD)
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// End of the date range (YYYY-MM-DD)
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        #[arg(short, long, value_enum, default_value = "table")]
+        output: OutputFormat,
+        #[arg(short, long)]
+        price_file: Option<String>,
+    },
+}
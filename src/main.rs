@@ -12,12 +12,15 @@
 mod interface;
 
 use clap::Parser;
-use interface::{Cli, Commands};
-use pricedb::model::SecurityFilter;
+use interface::{ActionsCmd, CacheCmd, Cli, Commands, FileCmd};
+use pricedb::{
+    cache::CacheMode,
+    model::{PriceFilter, PriceQuery, SecurityFilter},
+};
 
 //#[async_std::main]
 #[tokio::main]
-async fn main() {
+async fn main() -> anyhow::Result<()> {
     // initialize logging
     env_logger::init();
     log::trace!("starting");
@@ -39,6 +42,9 @@ async fn main() {
             agent,
             exchange,
             symbol,
+            no_cache,
+            refresh,
+            date,
         }) => {
             let filter = SecurityFilter {
                 currency: currency.clone(),
@@ -46,10 +52,130 @@ async fn main() {
                 exchange: exchange.clone(),
                 symbol: symbol.clone(),
             };
+            let cache_mode = CacheMode::from_flags(*no_cache, *refresh);
 
-            app.dl_quote(symbols_file, price_file, filter).await;
+            app.dl_quote(symbols_file, price_file, filter, cache_mode, *date).await?;
         }
 
+        Some(Commands::Add {
+            symbol,
+            value,
+            currency,
+            date,
+            time,
+            force,
+            price_file,
+            symbols_file,
+        }) => app.add_price(
+            symbols_file,
+            price_file,
+            symbol,
+            value,
+            currency,
+            date,
+            time,
+            *force,
+        )?,
+
+        Some(Commands::Show {
+            commodity,
+            date,
+            time,
+            from,
+            to,
+            history,
+            output,
+            price_file,
+            symbols_file,
+            currency,
+            agent,
+            exchange,
+            symbol,
+        }) => {
+            let query = PriceQuery {
+                prices: PriceFilter {
+                    symbol: commodity.clone(),
+                    date: date.clone(),
+                    time: time.clone(),
+                },
+                securities: SecurityFilter {
+                    currency: currency.clone(),
+                    agent: agent.clone(),
+                    exchange: exchange.clone(),
+                    symbol: symbol.clone(),
+                },
+                from: *from,
+                to: *to,
+                history: *history,
+            };
+
+            app.show_prices(symbols_file, price_file, &query, *output)?;
+        }
+
+        Some(Commands::Convert {
+            amount,
+            from,
+            to,
+            date,
+            price_file,
+        }) => app.show_conversion(price_file, amount, from, to, *date)?,
+
+        // file maintenance
+        Some(Commands::File(FileCmd::Merge {
+            files,
+            output,
+            prefer,
+        })) => app.file_merge(files, output, prefer)?,
+        Some(Commands::File(FileCmd::Split {
+            file,
+            by,
+            output_dir,
+        })) => app.file_split(file, *by, output_dir)?,
+        Some(Commands::File(FileCmd::Dedupe { file, output })) => app.file_dedupe(file, output)?,
+
+        // cache
+        Some(Commands::Cache(CacheCmd::List)) => app.cache_list()?,
+        Some(Commands::Cache(CacheCmd::Clear { expired })) => app.cache_clear(*expired)?,
+
+        Some(Commands::Daemon { list }) => app.daemon(*list).await?,
+
+        Some(Commands::Serve {
+            bind,
+            port,
+            price_file,
+            symbols_file,
+        }) => app.serve(symbols_file, price_file, bind, *port).await?,
+
+        // corporate actions
+        Some(Commands::Actions(ActionsCmd::Dl {
+            from,
+            to,
+            price_file,
+            symbols_file,
+            currency,
+            agent,
+            exchange,
+            symbol,
+        })) => {
+            let filter = SecurityFilter {
+                currency: currency.clone(),
+                agent: agent.clone(),
+                exchange: exchange.clone(),
+                symbol: symbol.clone(),
+            };
+
+            app.dl_actions(symbols_file, price_file, filter, *from, *to).await?;
+        }
+        Some(Commands::Actions(ActionsCmd::Show {
+            commodity,
+            from,
+            to,
+            output,
+            price_file,
+        })) => app.show_actions(price_file, commodity, *from, *to, *output)?,
+
         None => println!("No command issued."),
     }
+
+    Ok(())
 }
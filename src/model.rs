@@ -4,17 +4,21 @@
 
 use std::fmt::Display;
 
+use chrono::{FixedOffset, NaiveDate, NaiveDateTime};
 use rust_decimal::Decimal;
 
-#[derive(Debug, Default, Clone, Eq, PartialEq, PartialOrd, Ord)]
+use crate::price_flat_file::PriceRecord;
+
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
 pub struct Price {
     /// Symbol in format NAMESPACE:SYMBOL
     pub symbol: String,
     pub id: i64,
-    pub date: String,
-    pub time: String,
-    pub value: i64,
-    pub denom: i64,
+    /// Date and time of the price. Midnight when the source provides only the date.
+    pub datetime: NaiveDateTime,
+    /// UTC offset of the `datetime`, if known.
+    pub offset: Option<FixedOffset>,
+    pub value: Decimal,
     pub currency: String,
 }
 
@@ -22,22 +26,69 @@ impl Price {
     pub fn new() -> Self {
         Self::default()
     }
+}
+
+/// A corporate action of a security, i.e. a split or a dividend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorporateAction {
+    /// The ledger symbol, as in the price file.
+    pub symbol: String,
+    /// The ex-date.
+    pub date: NaiveDate,
+    pub kind: CorporateActionKind,
+}
 
-    pub fn to_decimal(&self) -> Decimal {
-        let scale = self.scale();
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CorporateActionKind {
+    /// The new shares (numerator) for the old shares (denominator), i.e. 10:1.
+    Split {
+        numerator: Decimal,
+        denominator: Decimal,
+    },
+    /// The amount per share.
+    Dividend { amount: Decimal, currency: String },
+}
 
-        Decimal::new(self.value, scale)
+impl CorporateActionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Split { .. } => "split",
+            Self::Dividend { .. } => "dividend",
+        }
     }
 
-    pub fn scale(&self) -> u32 {
-        let denom_f = self.denom as f64;
-        let scale = denom_f.log10();
+    /// The ratio of the split, or the amount of the dividend.
+    pub fn value(&self) -> String {
+        match self {
+            Self::Split {
+                numerator,
+                denominator,
+            } => format!("{numerator}:{denominator}"),
+            Self::Dividend { amount, .. } => amount.to_string(),
+        }
+    }
 
-        scale as u32
+    /// The currency of the dividend.
+    pub fn currency(&self) -> &str {
+        match self {
+            Self::Split { .. } => "",
+            Self::Dividend { currency, .. } => currency,
+        }
     }
+}
 
-    pub fn default_time() -> String {
-        "00:00:00".to_owned()
+impl Display for CorporateAction {
+    /// i.e. 2024-06-10 NVDA split 10:1, or 2024-03-15 VHY_AX dividend 0.85 AUD
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let line = format!(
+            "{} {} {} {} {}",
+            self.date,
+            self.symbol,
+            self.kind.name(),
+            self.kind.value(),
+            self.kind.currency()
+        );
+        write!(f, "{}", line.trim_end())
     }
 }
 
@@ -52,9 +103,28 @@ impl PriceFilter {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Checks the record against the filter.
+    /// The symbol is the ledger symbol, the date is YYYY-MM-DD, and the time HH:MM:SS.
+    pub fn matches(&self, record: &PriceRecord) -> bool {
+        let symbol_matches = match &self.symbol {
+            Some(symbol) => record.symbol == symbol.to_uppercase(),
+            None => true,
+        };
+        let date_matches = match &self.date {
+            Some(date) => record.datetime.date().to_string() == *date,
+            None => true,
+        };
+        let time_matches = match &self.time {
+            Some(time) => record.datetime.time().to_string() == *time,
+            None => true,
+        };
+
+        symbol_matches && date_matches && time_matches
+    }
 }
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct SecurityFilter {
     pub currency: Option<String>,
     pub agent: Option<String>,
@@ -71,9 +141,26 @@ impl SecurityFilter {
             symbol: None,
         }
     }
+
+    /// No filter values set.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::new()
+    }
 }
 
-#[derive(Debug)]
+/// Parameters for querying the stored prices.
+#[derive(Debug, Default)]
+pub struct PriceQuery {
+    pub prices: PriceFilter,
+    pub securities: SecurityFilter,
+    /// The date range, inclusive.
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// All the prices instead of only the latest price per symbol.
+    pub history: bool,
+}
+
+#[derive(Debug, Clone)]
 pub struct SecuritySymbol {
     pub namespace: String,
     pub mnemonic: String,
@@ -143,30 +230,31 @@ mod tests {
     }
 
     #[test]
-    fn scale_calculation() {
-        let mut p = Price::new();
-
-        // we need only the price values
-        p.value = 12345;
-        p.denom = 100;
-        assert_eq!(2, p.scale());
+    fn price_default_time() {
+        let p = Price::new();
 
-        p.value = 12345;
-        p.denom = 1000;
-        assert_eq!(3, p.scale());
+        assert_eq!("00:00:00", p.datetime.time().to_string());
+        assert_eq!(Decimal::ZERO, p.value);
     }
 
     #[test]
-    fn price_value() {
-        let mut p = Price::new();
-        // we need only the price values
-        p.value = 12345;
-        p.denom = 100;
-
-        let actual = p.to_decimal();
-
-        assert_eq!(actual, Decimal::from_str_exact("123.45").unwrap());
-        assert_eq!(actual.to_string(), "123.45");
+    fn test_price_filter() {
+        let record = PriceRecord {
+            datetime: NaiveDateTime::parse_from_str("2023-04-15 12:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap(),
+            symbol: "VEUR_AS".into(),
+            value: Decimal::ONE,
+            currency: "EUR".into(),
+        };
+        let mut filter = PriceFilter::new();
+        assert!(filter.matches(&record));
+
+        filter.symbol = Some("veur_as".into());
+        filter.date = Some("2023-04-15".into());
+        assert!(filter.matches(&record));
+
+        filter.time = Some("10:00:00".into());
+        assert!(!filter.matches(&record));
     }
 
     #[test]
@@ -2,16 +2,22 @@
 * Maintains the prices in a flat-file in Ledger format.
 * P 2023-04-14 00:00:00 GBP 1.132283 EUR
 */
-use std::{collections::HashMap, fs, fmt::Display};
-
-use chrono::NaiveDateTime;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::{self, File},
+    io::{BufRead, BufReader, Lines},
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{NaiveDate, NaiveDateTime};
 use rust_decimal::Decimal;
 
 use crate::model::Price;
 
 const DATE_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct PriceRecord {
     pub datetime: NaiveDateTime,
     pub symbol: String,
@@ -32,19 +38,20 @@ impl Display for PriceRecord {
     }
 }
 
+impl PriceRecord {
+    /// The key for the latest prices: symbol and currency, i.e. "AUD EUR".
+    /// A commodity can have prices in several currencies.
+    pub fn key(&self) -> String {
+        format!("{} {}", self.symbol, self.currency)
+    }
+}
+
 impl From<&Price> for PriceRecord {
     fn from(item: &Price) -> Self {
-        let time = if item.time == "" {
-            "00:00:00"
-        } else {
-            item.time.as_str()
-        };
-        let date_time = format!("{0} {1}", item.date, time);
-
         PriceRecord {
-            datetime: NaiveDateTime::parse_from_str(&date_time, DATE_TIME_FORMAT).expect("parsed date/time"),
+            datetime: item.datetime,
             symbol: item.symbol.to_owned(),
-            value: item.to_decimal(),
+            value: item.value,
             currency: item.currency.to_owned(),
         }
     }
@@ -53,53 +60,171 @@ impl From<&Price> for PriceRecord {
 #[derive(Default)]
 pub struct PriceFlatFile {
     file_path: String,
+    /// The latest prices, by `PriceRecord::key`.
     pub prices: HashMap<String, PriceRecord>,
+    /// The older prices, i.e. from `dl --date`. Saved as they are.
+    history: Vec<PriceRecord>,
 }
 
 impl PriceFlatFile {
     /// Load prices from a text file.
-    pub fn load(file_path: &str) -> Self {
-        let content = fs::read_to_string(file_path).expect("Error reading rates file");
-        // log::debug!("price file: {:?}", content);
-        let lines = content.lines();
-        // log::debug!("price lines: {:?}", lines);
-
-        //let mut prices = vec![];
+    /// Finds the latest price for each symbol and currency, and keeps the others.
+    pub fn load(file_path: &str) -> Result<Self> {
         let mut prices: HashMap<String, PriceRecord> = HashMap::new();
+        let mut history = vec![];
 
-        for line in lines {
-            let price = parse_line(line);
-            // prices.push(price);
-            prices.insert(price.symbol.to_owned(), price);
+        for record in load_records(file_path)? {
+            match prices.get(&record.key()) {
+                Some(latest) if latest.datetime > record.datetime => history.push(record),
+                _ => history.extend(prices.insert(record.key(), record)),
+            }
         }
 
-        Self {
+        Ok(Self {
             file_path: file_path.to_owned(),
-            prices: prices,
-        }
+            prices,
+            history,
+        })
+    }
+
+    /// Adds the price, replacing the latest one for the same symbol and currency.
+    pub fn insert(&mut self, record: PriceRecord) {
+        self.prices.insert(record.key(), record);
     }
 
     pub fn save(&self) {
-        // order by date/time
-        let mut sorted: Vec<(&String, &PriceRecord)> = self.prices.iter().collect();
-        sorted.sort_by_key(|item| (&item.1.datetime, &item.1.symbol));
+        let records: Vec<PriceRecord> =
+            self.history.iter().chain(self.prices.values()).cloned().collect();
+
+        save_records(&self.file_path, records);
+    }
+}
+
+/// Loads all the price records from a text file, in the order they appear.
+/// Unlike `PriceFlatFile::load`, does not look for the latest prices.
+pub fn load_records(file_path: &str) -> Result<Vec<PriceRecord>> {
+    PriceReader::open(file_path)?
+        .collect::<Result<_>>()
+        .with_context(|| format!("Could not read the price file {file_path}"))
+}
+
+/// Saves the price records into a text file, ordered by date/time and symbol.
+pub fn save_records(file_path: &str, mut records: Vec<PriceRecord>) {
+    sort_records(&mut records);
+
+    let mut output = String::default();
+
+    for price in records {
+        // log::debug!("price output {:?}", price.to_string());
+        output += &price.to_string();
+        output += "\n";
+    }
 
-        let mut output = String::default();
+    fs::write(file_path, output).expect("saved successfully");
+}
+
+/// Orders the records by date/time, symbol, and currency, the order used in the price files.
+pub fn sort_records(records: &mut [PriceRecord]) {
+    records.sort_by(|a, b| {
+        (&a.datetime, &a.symbol, &a.currency).cmp(&(&b.datetime, &b.symbol, &b.currency))
+    });
+}
+
+/// Reads the price records from a price file one line at a time,
+/// without loading the whole file into memory. Blank lines are skipped.
+pub struct PriceReader<R: BufRead> {
+    lines: Lines<R>,
+    line_number: usize,
+}
+
+impl PriceReader<BufReader<File>> {
+    pub fn open(file_path: &str) -> Result<Self> {
+        let file = File::open(file_path)
+            .with_context(|| format!("Could not open the price file {file_path}"))?;
+
+        Ok(Self::new(BufReader::new(file)))
+    }
+}
+
+impl<R: BufRead> PriceReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line_number: 0,
+        }
+    }
+
+    /// The latest price (by date/time) for each symbol and currency.
+    /// Holds only one record per symbol in memory.
+    pub fn latest(self) -> Result<HashMap<String, PriceRecord>> {
+        latest_prices(self)
+    }
+
+    /// The prices in the given date range. Both dates are inclusive, and optional.
+    pub fn between(
+        self,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> impl Iterator<Item = Result<PriceRecord>> {
+        self.filter(move |record| match record {
+            Ok(record) => {
+                let date = record.datetime.date();
+
+                from.is_none_or(|from| date >= from) && to.is_none_or(|to| date <= to)
+            }
+            // pass the errors through
+            Err(_) => true,
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for PriceReader<R> {
+    type Item = Result<PriceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line_number += 1;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let line_number = self.line_number;
+            return Some(
+                parse_line(&line).with_context(|| format!("Invalid price on line {line_number}")),
+            );
+        }
+    }
+}
 
-        // self.prices.values()
-        for price in sorted {
-            // log::debug!("price output {:?}", price.1.to_string());
-            output += &price.1.to_string();
-            output += "\n";
+/// Picks the latest price (by date/time) for each symbol and currency from the records.
+/// The result is keyed by `PriceRecord::key`.
+pub fn latest_prices(
+    records: impl Iterator<Item = Result<PriceRecord>>,
+) -> Result<HashMap<String, PriceRecord>> {
+    let mut latest: HashMap<String, PriceRecord> = HashMap::new();
+
+    for record in records {
+        let record = record?;
+
+        match latest.get(&record.key()) {
+            Some(existing) if existing.datetime > record.datetime => (),
+            _ => {
+                latest.insert(record.key(), record);
+            }
         }
-        
-        fs::write(&self.file_path, output).expect("saved successfully");
     }
+
+    Ok(latest)
 }
 
 /// Parses price line
 /// P 2023-04-14 00:00:00 GBP 1.132283 EUR
-fn parse_line(line: &str) -> PriceRecord {
+fn parse_line(line: &str) -> Result<PriceRecord> {
     let line_parts: Vec<&str> = line.split_whitespace().collect();
     // log::debug!("line parts: {:?}", line_parts);
 
@@ -107,45 +232,37 @@ fn parse_line(line: &str) -> PriceRecord {
     // log::debug!("parts count: {:?}", parts_num);
 
     // parse
-    let result;
-    if parts_num == 5 {
+    match parts_num {
         // 5 parts => no time
-        result = parse_with_no_time(&line_parts);
-    } else if parts_num == 6 {
+        5 => parse_with_no_time(&line_parts),
         // 6 parts => have time
-        result = parse_with_time(&line_parts);
-    } else {
-        panic!("invalid number of parts parsed from the line!");
+        6 => parse_with_time(&line_parts),
+        _ => Err(anyhow!("invalid number of parts parsed from the line: {line}")),
     }
-    // log::debug!("result: {:?}", result);
-
-    result
 }
 
-fn parse_with_time(items: &Vec<&str>) -> PriceRecord {
+fn parse_with_time(items: &[&str]) -> Result<PriceRecord> {
     // now add time
-    let date_time_string = format!("{0} {1}", items[1].to_owned(), items[2].to_owned());
+    let date_time_string = format!("{0} {1}", items[1], items[2]);
     // log::debug!("date time string: {:?}", date_time_string);
 
-    PriceRecord {
-        datetime: NaiveDateTime::parse_from_str(&date_time_string, DATE_TIME_FORMAT)
-            .expect("parsed date/time"),
+    Ok(PriceRecord {
+        datetime: NaiveDateTime::parse_from_str(&date_time_string, DATE_TIME_FORMAT)?,
         symbol: items[3].to_owned(),
-        value: Decimal::from_str_exact(items[4]).expect("parsed value"),
+        value: Decimal::from_str_exact(items[4])?,
         currency: items[5].to_owned(),
-    }
+    })
 }
 
-fn parse_with_no_time(items: &Vec<&str>) -> PriceRecord {
-    let date_time_string = format!("{0} 00:00:00", items[1].to_owned());
+fn parse_with_no_time(items: &[&str]) -> Result<PriceRecord> {
+    let date_time_string = format!("{0} 00:00:00", items[1]);
 
-    PriceRecord {
-        datetime: NaiveDateTime::parse_from_str(&date_time_string, DATE_TIME_FORMAT)
-            .expect("parsed date"),
+    Ok(PriceRecord {
+        datetime: NaiveDateTime::parse_from_str(&date_time_string, DATE_TIME_FORMAT)?,
         symbol: items[2].to_owned(),
-        value: Decimal::from_str_exact(items[3]).expect("parsed value"),
+        value: Decimal::from_str_exact(items[3])?,
         currency: items[4].to_owned(),
-    }
+    })
 }
 
 #[cfg(test)]
@@ -155,7 +272,15 @@ mod tests {
 
     use crate::{price_flat_file::{PriceFlatFile, DATE_TIME_FORMAT}, model::Price};
 
-    use super::PriceRecord;
+    use super::{PriceReader, PriceRecord};
+
+    const HISTORY: &str = "P 2023-03-11 USD 1.11 EUR
+P 2023-04-14 GBP 1.132283 EUR
+
+P 2023-04-15 12:00:00 VEUR_AS 1.5 EUR
+P 2023-04-16 USD 1.12 EUR
+P 2023-04-12 GBP 1.13 EUR
+";
 
     #[test]
     fn test_parsing_date_time() {
@@ -176,28 +301,28 @@ mod tests {
     #[test]
     fn test_parsing_empty_time() {
         // Price -> PriceRecord
+        let date_time_string = "2023-03-04 00:00:00";
+        let date_time = NaiveDateTime::parse_from_str(&date_time_string, DATE_TIME_FORMAT);
+        assert!(date_time.is_ok());
+
         let price = Price {
             symbol: "HY".into(),
             id: 1,
-            date: "2023-03-04".into(),
-            time: "".into(),
-            value: 150.into(),
-            denom: 10,
+            datetime: date_time.unwrap(),
+            offset: None,
+            value: Decimal::new(150, 1),
             currency: "EUR".into(),
         };
 
-        let date_time_string = "2023-03-04 00:00:00";
-        let result = NaiveDateTime::parse_from_str(&date_time_string, DATE_TIME_FORMAT);
-        assert!(result.is_ok());
-
         let actual = PriceRecord::from(&price);
 
-        assert_eq!(result.unwrap(), actual.datetime);
+        assert_eq!(price.datetime, actual.datetime);
+        assert_eq!("P 2023-03-04 HY 15.0 EUR", actual.to_string());
     }
 
     #[test]
     fn test_load() {
-        let actual = PriceFlatFile::load("tests/prices.txt");
+        let actual = PriceFlatFile::load("tests/prices.txt").unwrap();
 
         // test the number of records in the file.
         assert_eq!(3, actual.prices.len());
@@ -288,4 +413,92 @@ mod tests {
 
         assert_eq!("P 2023-04-15 10:00:00 VEUR_AS 13.24 EUR", actual);
     }
+
+    #[test]
+    fn test_reader_latest() {
+        let reader = PriceReader::new(HISTORY.as_bytes());
+
+        let actual = reader.latest().expect("parsed");
+
+        assert_eq!(3, actual.len());
+        assert_eq!("P 2023-04-16 USD 1.12 EUR", actual["USD EUR"].to_string());
+        // out of order in the file
+        assert_eq!("P 2023-04-14 GBP 1.132283 EUR", actual["GBP EUR"].to_string());
+    }
+
+    #[test]
+    fn test_reader_between() {
+        let from = chrono::NaiveDate::from_ymd_opt(2023, 4, 14);
+        let to = chrono::NaiveDate::from_ymd_opt(2023, 4, 15);
+
+        let actual: Vec<PriceRecord> = PriceReader::new(HISTORY.as_bytes())
+            .between(from, to)
+            .collect::<anyhow::Result<_>>()
+            .expect("parsed");
+
+        assert_eq!(2, actual.len());
+        assert_eq!("GBP", actual[0].symbol);
+        assert_eq!("VEUR_AS", actual[1].symbol);
+    }
+
+    #[test]
+    fn test_reader_error_line() {
+        let content = "P 2023-04-14 GBP 1.13 EUR\nP 2023-04-15 GBP\n";
+
+        let actual: Vec<_> = PriceReader::new(content.as_bytes()).collect();
+
+        assert!(actual[0].is_ok());
+        let error = actual[1].as_ref().unwrap_err();
+        assert_eq!("Invalid price on line 2", error.to_string());
+    }
+
+    #[test]
+    fn test_insert_other_currency() {
+        let mut prices_file = PriceFlatFile::default();
+        let date_time = NaiveDateTime::parse_from_str("2023-03-04 00:00:00", DATE_TIME_FORMAT)
+            .expect("date");
+        let in_eur = PriceRecord {
+            datetime: date_time,
+            symbol: "AUD".into(),
+            value: Decimal::from_str_exact("0.6").unwrap(),
+            currency: "EUR".into(),
+        };
+        let in_usd = PriceRecord {
+            currency: "USD".into(),
+            value: Decimal::from_str_exact("0.66").unwrap(),
+            ..in_eur.clone()
+        };
+
+        prices_file.insert(in_eur);
+        prices_file.insert(in_usd.clone());
+        prices_file.insert(in_usd);
+
+        assert_eq!(2, prices_file.prices.len());
+    }
+
+    #[test]
+    fn test_update_keeps_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prices.txt");
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "P 2024-01-30 AUD 0.61 EUR\nP 2024-01-31 AUD 0.62 EUR\nP 2024-01-31 GBP 1.17 EUR\n",
+        )
+        .unwrap();
+
+        let mut prices_file = PriceFlatFile::load(path).unwrap();
+        assert_eq!("0.62", prices_file.prices["AUD EUR"].value.to_string());
+        let mut latest = prices_file.prices["AUD EUR"].clone();
+        latest.datetime = NaiveDateTime::parse_from_str("2024-02-01 00:00:00", DATE_TIME_FORMAT).unwrap();
+        latest.value = Decimal::from_str_exact("0.63").unwrap();
+        prices_file.insert(latest);
+        prices_file.save();
+
+        // the latest price is replaced, the older one is kept
+        assert_eq!(
+            "P 2024-01-30 AUD 0.61 EUR\nP 2024-01-31 GBP 1.17 EUR\nP 2024-02-01 AUD 0.63 EUR\n",
+            std::fs::read_to_string(path).unwrap()
+        );
+    }
 }
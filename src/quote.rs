@@ -5,27 +5,46 @@
  * Based on [Price Database](https://gitlab.com/alensiljak/price-database),
  * Python library.
  */
+mod cache;
+mod coingecko;
+mod exec;
 mod fixerio;
+mod html_page;
+mod json_provider;
+mod registry;
+mod stooq;
 mod vanguard_au_2023_detail;
 mod yahoo_finance_downloader;
+mod yahoo_v2;
 
-use anyhow::Result;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
+use chrono::{Local, NaiveDate};
+use rust_decimal::Decimal;
+use serde_json::Value;
 
 use crate::{
-    model::{Price, SecuritySymbol},
-    quote::{
-        fixerio::Fixerio, vanguard_au_2023_detail::VanguardAu3Downloader,
-        yahoo_finance_downloader::YahooFinanceDownloader,
-    },
+    cache::CacheMode,
+    config::PriceDbConfig,
+    model::{CorporateAction, Price, SecuritySymbol},
+    quote::{cache::PriceCache, fixerio::Fixerio},
 };
 
+pub use registry::{DownloaderFactory, DownloaderRegistry};
+
 #[derive(Debug)]
 pub struct Quote {
     pub symbol: Option<String>,
     pub exchange: Option<String>,
     pub source: Option<String>,
     pub currency: Option<String>,
+    config: PriceDbConfig,
+    cache_mode: CacheMode,
+    registry: DownloaderRegistry,
+    /// The date of the historical prices. The latest prices, if not set.
+    date: Option<NaiveDate>,
 }
 
 impl Quote {
@@ -35,34 +54,17 @@ impl Quote {
             exchange: None,
             source: None,
             currency: None,
+            config: PriceDbConfig::default(),
+            cache_mode: CacheMode::default(),
+            registry: DownloaderRegistry::default(),
+            date: None,
         }
     }
 
-    /// Fetch prices for the given symbols.
-    pub async fn fetch(&self, exchange: &str, symbols: Vec<&String>) -> Vec<Price> {
-        let mut result = vec![];
-
-        for symbol in symbols {
-            // log::debug!("Downloading price for {:?}", symbol);
-            let sec_sym = SecuritySymbol::new_separated(&exchange, &symbol);
-
-            let price = self
-                .download(&sec_sym)
-                .await
-                .expect("Did not receive price");
-            result.push(price);
-        }
-
-        result
-    }
-
-    // Private
-
-    async fn download(&self, security_symbol: &SecuritySymbol) -> Option<Price> {
-        // if exchange != exchange.to_uppercase() {
-        //     panic!("handle this case!");
-        // }
-
+    /// Fetch prices for the given symbols, from the source (agent) in the currency.
+    /// The results are in the order of the symbols. The symbols not in the cache are
+    /// requested together, if the provider supports it.
+    pub async fn fetch(&self, symbols: &[SecuritySymbol]) -> Vec<Result<Price>> {
         if self.currency.is_some() {
             let currency_val = self.currency.clone().unwrap();
             if currency_val != currency_val.to_uppercase() {
@@ -70,55 +72,120 @@ impl Quote {
             }
         }
 
-        // let sec_symbol = SecuritySymbol {
-        //     namespace: exchange.to_owned(),
-        //     mnemonic: symbol.to_owned(),
-        // };
-        // todo: parse symbol
-
-        let actor = self.get_downloader();
+        let agent = self.source.as_ref().unwrap().as_str();
         let currency = self.currency.as_ref().unwrap().as_str();
 
+        let date = self.date.unwrap_or_else(|| Local::now().date_naive());
+
+        let cache = PriceCache::new(&self.config, self.cache_mode);
+        let mut result: Vec<Option<Result<Price>>> = symbols
+            .iter()
+            .map(|symbol| cache.get(agent, symbol, currency, date).map(Ok))
+            .collect();
+
+        let missing: Vec<SecuritySymbol> = symbols
+            .iter()
+            .zip(&result)
+            .filter(|(_, cached)| cached.is_none())
+            .map(|(symbol, _)| symbol.to_owned())
+            .collect();
+        if missing.is_empty() {
+            return result.into_iter().flatten().collect();
+        }
+
+        let actor = match self.get_downloader() {
+            Ok(actor) => actor,
+            Err(error) => {
+                return result
+                    .into_iter()
+                    .map(|cached| cached.unwrap_or_else(|| Err(anyhow!("{error:#}"))))
+                    .collect()
+            }
+        };
+
         log::debug!(
-            "Calling download with symbol {} and currency {}",
-            security_symbol,
+            "Calling download with {} symbol(s) and currency {}",
+            missing.len(),
             currency
         );
 
-        let result = actor.download(security_symbol, currency).await;
-        //.expect("downloaded price");
-        match result {
-            Err(error) => {
-                // let x = result.err().unwrap();
-                panic!("Error downloading price: {}", error);
+        let downloaded = match self.date {
+            Some(date) => {
+                let mut prices = vec![];
+                for symbol in &missing {
+                    prices.push(actor.download_historical(symbol, currency, date).await);
+                }
+                prices
             }
-            Ok(mut price) => {
-                // Set the symbol here.
-                price.symbol = security_symbol.to_string();
+            None => actor.download_batch(&missing, currency).await,
+        };
+
+        let mut downloaded = missing.iter().zip(downloaded).map(|(symbol, price)| {
+            let mut price = price.with_context(|| format!("Error downloading price for {symbol}"))?;
+            // Set the symbol here.
+            price.symbol = symbol.to_string();
 
-                Some(price)
+            if let Err(error) = cache.put(agent, symbol, currency, date, &price) {
+                log::warn!("Could not cache the price for {symbol}: {error}");
             }
+
+            Ok(price)
+        });
+
+        for item in result.iter_mut().filter(|cached| cached.is_none()) {
+            *item = Some(downloaded.next().unwrap_or_else(|| Err(anyhow!("No price received"))));
         }
+
+        result.into_iter().flatten().collect()
     }
 
-    fn get_downloader(&self) -> Box<dyn Downloader> {
-        match self.source.as_ref().unwrap().as_str() {
-            "yahoo_finance" => {
-                log::trace!("using yahoo finance");
-                Box::new(YahooFinanceDownloader::new())
-            }
-            "fixerio" => {
-                log::trace!("using fixerio");
-                Box::new(Fixerio::new())
-            }
-            "vanguard_au" => {
-                log::trace!("using vanguard");
-                Box::new(VanguardAu3Downloader::new())
-            }
-            _ => {
-                panic!("unknown downloader: {}", self.source.as_ref().unwrap());
-            }
-        }
+    /// Derives the cross rates for the (commodity, currency) pairs from the Fixer rates
+    /// in the base currency.
+    pub async fn fetch_cross_rates(
+        &self,
+        base_currency: &str,
+        pairs: &[(String, String)],
+    ) -> Result<Vec<Price>> {
+        let mut fixerio = Fixerio::new(&self.config);
+        fixerio.set_cache_mode(self.cache_mode);
+
+        fixerio.cross_rates(base_currency, pairs).await
+    }
+
+    /// Fetch the corporate actions of the symbol between the dates, from the source.
+    pub async fn fetch_actions(
+        &self,
+        symbol: &SecuritySymbol,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<CorporateAction>> {
+        self.get_downloader()?.download_actions(symbol, from, to).await
+    }
+
+    /// Whether the source (agent) can download the prices on a past date.
+    pub fn supports_historical(&self) -> Result<bool> {
+        Ok(self.get_downloader()?.supports_historical())
+    }
+
+    // Private
+
+    /// The downloader from the registry, including the JSON providers from the
+    /// configuration.
+    fn get_downloader(&self) -> Result<Arc<dyn Downloader>> {
+        let agent = self.source.as_deref().unwrap_or_default();
+        log::trace!("using {agent}");
+
+        let mut registry = self.registry.clone();
+        registry.register_json_providers(&self.config.json_providers)?;
+
+        registry
+            .create(agent, &self.config, self.cache_mode)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Unknown agent (updater) '{agent}'. The available agents are: {}",
+                    registry.agents().join(", ")
+                )
+            })
     }
 
     // fn currency() {}
@@ -130,9 +197,206 @@ impl Quote {
     pub fn set_source(&mut self, source: &str) {
         self.source = Some(source.to_string());
     }
+
+    /// Download the prices on the given date instead of the latest ones.
+    pub fn set_date(&mut self, date: Option<NaiveDate>) {
+        self.date = date;
+    }
+
+    pub fn set_cache_mode(&mut self, cache_mode: CacheMode) {
+        self.cache_mode = cache_mode;
+    }
+
+    /// The configuration for the downloaders, i.e. the symbol mappings.
+    pub fn set_config(&mut self, config: &PriceDbConfig) {
+        self.config = config.clone();
+    }
+
+    /// The downloaders to use, by agent name. The built-in ones by default.
+    pub fn set_registry(&mut self, registry: &DownloaderRegistry) {
+        self.registry = registry.clone();
+    }
 }
 
+/// A price provider. Implement it, and register it in the `DownloaderRegistry`, to add
+/// an agent.
 #[async_trait]
-trait Downloader {
+pub trait Downloader: Send + Sync {
+    /// The latest price.
     async fn download(&self, security_symbol: &SecuritySymbol, currency: &str) -> Result<Price>;
+
+    /// The latest prices of the symbols, in the same currency, in the order of the
+    /// symbols. One by one, unless the provider supports several symbols per request.
+    async fn download_batch(
+        &self,
+        symbols: &[SecuritySymbol],
+        currency: &str,
+    ) -> Vec<Result<Price>> {
+        let mut result = vec![];
+        for symbol in symbols {
+            result.push(self.download(symbol, currency).await);
+        }
+        result
+    }
+
+    /// Whether the provider implements `download_historical`.
+    fn supports_historical(&self) -> bool {
+        false
+    }
+
+    /// The (daily) price on the given date.
+    async fn download_historical(
+        &self,
+        security_symbol: &SecuritySymbol,
+        _currency: &str,
+        _date: NaiveDate,
+    ) -> Result<Price> {
+        Err(anyhow!("Historical prices are not supported for {security_symbol}"))
+    }
+
+    /// The splits and dividends between the dates, inclusive.
+    async fn download_actions(
+        &self,
+        security_symbol: &SecuritySymbol,
+        _from: NaiveDate,
+        _to: NaiveDate,
+    ) -> Result<Vec<CorporateAction>> {
+        Err(anyhow!("Corporate actions are not supported for {security_symbol}"))
+    }
+}
+
+/// Parses a JSON number (or a numeric string) exactly into a Decimal.
+/// Relies on serde_json's `arbitrary_precision`, which keeps the original number text,
+/// to avoid the f64 round-trip.
+pub(crate) fn parse_decimal(value: &Value) -> Result<Decimal> {
+    let text = match value {
+        Value::Number(number) => number.to_string(),
+        Value::String(text) => text.trim().to_owned(),
+        _ => return Err(anyhow!("Not a number: {}", value)),
+    };
+
+    let result = if text.contains(['e', 'E']) {
+        Decimal::from_scientific(&text)?
+    } else {
+        Decimal::from_str_exact(&text)?
+    };
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use rust_decimal_macros::dec;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// Records the batches.
+    #[derive(Default)]
+    struct BatchRecorder {
+        batches: Mutex<Vec<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Downloader for BatchRecorder {
+        async fn download(&self, _security_symbol: &SecuritySymbol, _currency: &str) -> Result<Price> {
+            Err(anyhow!("not batched"))
+        }
+
+        async fn download_batch(&self, symbols: &[SecuritySymbol], currency: &str) -> Vec<Result<Price>> {
+            self.batches
+                .lock()
+                .unwrap()
+                .push(symbols.iter().map(|symbol| symbol.to_string()).collect());
+
+            symbols
+                .iter()
+                .map(|symbol| match symbol.mnemonic.as_str() {
+                    "MISSING" => Err(anyhow!("no price")),
+                    _ => Ok(Price {
+                        value: dec!(1.5),
+                        currency: currency.to_owned(),
+                        ..Price::default()
+                    }),
+                })
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = PriceDbConfig {
+            cache_path: dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let recorder = Arc::new(BatchRecorder::default());
+        let mut registry = DownloaderRegistry::new();
+        registry.register_downloader("batch", recorder.clone());
+
+        let mut quote = Quote::new();
+        quote.set_config(&config);
+        quote.set_registry(&registry);
+        quote.set_source("batch");
+        quote.set_currency("EUR");
+
+        // cached
+        let cached = SecuritySymbol::new("XETRA:CACHED");
+        let cached_price = Price {
+            symbol: cached.to_string(),
+            value: dec!(2),
+            currency: "EUR".into(),
+            ..Price::default()
+        };
+        PriceCache::new(&config, CacheMode::Use)
+            .put("batch", &cached, "EUR", Local::now().date_naive(), &cached_price)
+            .unwrap();
+
+        let symbols = [
+            SecuritySymbol::new("XETRA:EL4X"),
+            cached,
+            SecuritySymbol::new("XETRA:MISSING"),
+        ];
+
+        let actual = quote.fetch(&symbols).await;
+
+        assert_eq!(
+            vec![vec!["XETRA:EL4X".to_string(), "XETRA:MISSING".to_string()]],
+            *recorder.batches.lock().unwrap()
+        );
+        assert_eq!("XETRA:EL4X", actual[0].as_ref().unwrap().symbol);
+        assert_eq!(dec!(2), actual[1].as_ref().unwrap().value);
+        assert!(actual[2].is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_agent() {
+        let mut quote = Quote::new();
+        quote.set_source("yahoo_financ");
+        quote.set_currency("EUR");
+        quote.set_cache_mode(CacheMode::Off);
+
+        let actual = quote.fetch(&[SecuritySymbol::new("XETRA:EL4X")]).await;
+
+        let error = actual[0].as_ref().unwrap_err().to_string();
+        assert!(error.contains("Unknown agent (updater) 'yahoo_financ'"));
+        assert!(error.contains("yahoo_finance"));
+    }
+
+    #[test]
+    fn test_parse_decimal_exact() {
+        let body: Value = serde_json::from_str(r#"{"a": 13.24, "b": 0.1, "c": 1.05e-3}"#).unwrap();
+
+        assert_eq!(dec!(13.24), parse_decimal(&body["a"]).unwrap());
+        assert_eq!("0.1", parse_decimal(&body["b"]).unwrap().to_string());
+        assert_eq!(dec!(0.00105), parse_decimal(&body["c"]).unwrap());
+    }
+
+    #[test]
+    fn test_parse_decimal_string() {
+        assert_eq!(dec!(75.2041), parse_decimal(&json!("75.2041")).unwrap());
+        assert!(parse_decimal(&Value::Null).is_err());
+    }
 }
@@ -0,0 +1,220 @@
+/*!
+ * Provider-independent cache of the downloaded prices.
+ *
+ * Each price is cached per (agent, symbol, currency, date), so that reruns on the
+ * same day do not call the providers again until the entry expires. The date is the
+ * date of the request, or the date of the requested historical price.
+ */
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use chrono::{FixedOffset, NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+
+use crate::{
+    cache::{self, CacheMode},
+    config::PriceDbConfig,
+    model::{Price, SecuritySymbol},
+};
+
+const DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+pub(crate) struct PriceCache {
+    cache_dir: PathBuf,
+    config: PriceDbConfig,
+    mode: CacheMode,
+}
+
+impl PriceCache {
+    pub fn new(config: &PriceDbConfig, mode: CacheMode) -> Self {
+        Self {
+            cache_dir: cache::get_cache_dir(config),
+            config: config.clone(),
+            mode,
+        }
+    }
+
+    /// The cached price, if it exists and has not expired.
+    pub fn get(
+        &self,
+        agent: &str,
+        symbol: &SecuritySymbol,
+        currency: &str,
+        date: NaiveDate,
+    ) -> Option<Price> {
+        if !self.mode.reads() {
+            return None;
+        }
+
+        let path = self.get_path(agent, symbol, currency, date);
+        let expiry = cache::get_provider_expiry(&self.config, agent);
+        let content = cache::read(&path, expiry)?;
+
+        match from_json(&content) {
+            Ok(price) => {
+                log::debug!("Using the cached price for {symbol} from {}", path.display());
+                Some(price)
+            }
+            Err(error) => {
+                log::warn!("Ignoring the invalid cache entry {}: {error}", path.display());
+                None
+            }
+        }
+    }
+
+    pub fn put(
+        &self,
+        agent: &str,
+        symbol: &SecuritySymbol,
+        currency: &str,
+        date: NaiveDate,
+        price: &Price,
+    ) -> Result<()> {
+        if !self.mode.writes() {
+            return Ok(());
+        }
+
+        let path = self.get_path(agent, symbol, currency, date);
+
+        cache::write(&path, &to_json(price).to_string())
+    }
+
+    /// The cache file for the key, i.e. price_yahoo%5Ffinance_XETRA_EL4X_EUR_2024-02-03.json
+    fn get_path(&self, agent: &str, symbol: &SecuritySymbol, currency: &str, date: NaiveDate) -> PathBuf {
+        let key = [agent, &symbol.namespace, &symbol.mnemonic, currency]
+            .map(encode)
+            .join("_");
+
+        self.cache_dir.join(format!("price_{key}_{}.json", date.format("%Y-%m-%d")))
+    }
+}
+
+/// Percent-encodes the key part for the file name. The encoding is reversible, so that
+/// the different keys, i.e. A.B and A_B, do not share a file.
+fn encode(part: &str) -> String {
+    part.bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || byte == b'-' {
+                char::from(byte).to_string()
+            } else {
+                format!("%{byte:02X}")
+            }
+        })
+        .collect()
+}
+
+fn to_json(price: &Price) -> Value {
+    json!({
+        "symbol": price.symbol,
+        "datetime": price.datetime.format(DATETIME_FORMAT).to_string(),
+        "offset": price.offset.map(|offset| offset.to_string()),
+        "value": price.value.to_string(),
+        "currency": price.currency,
+    })
+}
+
+fn from_json(content: &str) -> Result<Price> {
+    let value: Value = serde_json::from_str(content)?;
+    let text = |name: &str| -> Result<String> {
+        value[name]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow!("Missing {name}"))
+    };
+
+    let offset = match value["offset"].as_str() {
+        Some(offset) => Some(offset.parse::<FixedOffset>()?),
+        None => None,
+    };
+
+    Ok(Price {
+        symbol: text("symbol")?,
+        id: i64::default(),
+        datetime: NaiveDateTime::parse_from_str(&text("datetime")?, DATETIME_FORMAT)?,
+        offset,
+        value: Decimal::from_str_exact(&text("value")?)?,
+        currency: text("currency")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn config(dir: &tempfile::TempDir) -> PriceDbConfig {
+        PriceDbConfig {
+            cache_path: dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn price() -> Price {
+        Price {
+            symbol: "XETRA:EL4X".into(),
+            datetime: NaiveDateTime::parse_from_str("2024-02-02T17:35:00", DATETIME_FORMAT).unwrap(),
+            offset: FixedOffset::east_opt(3600),
+            value: dec!(13.240),
+            currency: "EUR".into(),
+            ..Default::default()
+        }
+    }
+
+    fn today() -> NaiveDate {
+        chrono::Local::now().date_naive()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PriceCache::new(&config(&dir), CacheMode::Use);
+        let symbol = SecuritySymbol::new("XETRA:EL4X");
+
+        cache.put("yahoo_finance", &symbol, "EUR", today(), &price()).unwrap();
+
+        assert_eq!(Some(price()), cache.get("yahoo_finance", &symbol, "EUR", today()));
+        // a different key
+        assert_eq!(None, cache.get("yahoo_finance", &symbol, "USD", today()));
+        assert_eq!(None, cache.get("vanguard_au", &symbol, "EUR", today()));
+        assert_eq!(None, cache.get("yahoo_finance", &symbol, "EUR", today().pred_opt().unwrap()));
+    }
+
+    #[test]
+    fn test_distinct_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = PriceCache::new(&config(&dir), CacheMode::Use);
+        let path = |mnemonic: &str| {
+            let symbol = SecuritySymbol::new_separated("LSE", mnemonic);
+            cache.get_path("yahoo_finance", &symbol, "GBP", today())
+        };
+
+        assert_ne!(path("A.B"), path("A_B"));
+        assert_ne!(path("A B"), path("A_B"));
+        let name = format!("price_yahoo%5Ffinance_LSE_A%2EB_GBP_{}.json", today());
+        assert!(path("A.B").ends_with(name));
+
+        let symbol = SecuritySymbol::new_separated("LSE", "A.B");
+        cache.put("yahoo_finance", &symbol, "GBP", today(), &price()).unwrap();
+        let other = SecuritySymbol::new_separated("LSE", "A_B");
+        assert_eq!(None, cache.get("yahoo_finance", &other, "GBP", today()));
+        assert_eq!(1, cache::list(dir.path()).unwrap().len());
+    }
+
+    #[test]
+    fn test_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let symbol = SecuritySymbol::new("XETRA:EL4X");
+
+        PriceCache::new(&config(&dir), CacheMode::Off)
+            .put("yahoo_finance", &symbol, "EUR", today(), &price())
+            .unwrap();
+        assert!(cache::list(dir.path()).unwrap().is_empty());
+
+        let refresh = PriceCache::new(&config(&dir), CacheMode::Refresh);
+        refresh.put("yahoo_finance", &symbol, "EUR", today(), &price()).unwrap();
+        assert_eq!(None, refresh.get("yahoo_finance", &symbol, "EUR", today()));
+        assert_eq!(1, cache::list(dir.path()).unwrap().len());
+    }
+}
@@ -0,0 +1,256 @@
+/*!
+ * CoinGecko cryptocurrency prices.
+ *
+ * The latest price:
+ * https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=eur&include_last_updated_at=true
+ * The daily (00:00 UTC) historical price:
+ * https://api.coingecko.com/api/v3/coins/bitcoin/history?date=31-01-2024&localization=false
+ *
+ * The symbols, i.e. CRYPTO:BTC, are mapped to the CoinGecko coin ids (bitcoin).
+ * Symbols not in the mapping are used as the coin id, so the id can also be set as
+ * the `updater_symbol`.
+ */
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Offset, Utc};
+use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
+use serde_json::Value;
+
+use crate::model::{Price, SecuritySymbol};
+
+use super::{parse_decimal, Downloader};
+
+pub struct CoinGeckoDownloader {
+    url: String,
+    api_key: String,
+    coins: HashMap<String, String>,
+}
+
+impl CoinGeckoDownloader {
+    pub fn new() -> Self {
+        let coins = [
+            ("ADA", "cardano"),
+            ("BTC", "bitcoin"),
+            ("DOGE", "dogecoin"),
+            ("DOT", "polkadot"),
+            ("ETH", "ethereum"),
+            ("LTC", "litecoin"),
+            ("SOL", "solana"),
+            ("USDC", "usd-coin"),
+            ("USDT", "tether"),
+            ("XRP", "ripple"),
+        ]
+        .into_iter()
+        .map(|(symbol, id)| (symbol.to_owned(), id.to_owned()))
+        .collect();
+
+        Self {
+            url: "https://api.coingecko.com/api/v3".to_string(),
+            api_key: String::default(),
+            coins,
+        }
+    }
+
+    /// Adds the given symbol -> coin id mapping over the built-in one.
+    pub fn add_coins(&mut self, custom_coins: &HashMap<String, String>) {
+        for (symbol, id) in custom_coins {
+            self.coins.insert(symbol.to_uppercase(), id.to_lowercase());
+        }
+    }
+
+    /// The (demo) API key, sent in the x-cg-demo-api-key header.
+    pub fn set_api_key(&mut self, api_key: &str) {
+        self.api_key = api_key.to_owned();
+    }
+
+    fn get_coin_id(&self, symbol: &SecuritySymbol) -> String {
+        match self.coins.get(&symbol.mnemonic) {
+            Some(id) => id.to_owned(),
+            None => symbol.mnemonic.to_lowercase(),
+        }
+    }
+
+    fn latest_url(&self, coin_id: &str, currency: &str) -> String {
+        format!(
+            "{}/simple/price?ids={coin_id}&vs_currencies={}&include_last_updated_at=true",
+            self.url,
+            currency.to_lowercase()
+        )
+    }
+
+    fn history_url(&self, coin_id: &str, date: NaiveDate) -> String {
+        format!(
+            "{}/coins/{coin_id}/history?date={}&localization=false",
+            self.url,
+            date.format("%d-%m-%Y")
+        )
+    }
+
+    async fn get_json(&self, url: &str) -> Result<Value> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static("pricedb"));
+        if !self.api_key.is_empty() {
+            headers.insert("x-cg-demo-api-key", self.api_key.parse()?);
+        }
+
+        log::debug!("fetching from {:?}", url);
+
+        let response = reqwest::Client::new().get(url).headers(headers).send().await?;
+        let status = response.status();
+        let body: Value = response.json().await?;
+
+        if !status.is_success() {
+            return Err(anyhow!("CoinGecko error {status}: {}", get_error(&body)));
+        }
+
+        Ok(body)
+    }
+}
+
+#[async_trait]
+impl Downloader for CoinGeckoDownloader {
+    async fn download(&self, security_symbol: &SecuritySymbol, currency: &str) -> Result<Price> {
+        let coin_id = self.get_coin_id(security_symbol);
+        let body = self.get_json(&self.latest_url(&coin_id, currency)).await?;
+
+        map_latest_price(&body, &coin_id, currency)
+    }
+
+    fn supports_historical(&self) -> bool {
+        true
+    }
+
+    async fn download_historical(
+        &self,
+        security_symbol: &SecuritySymbol,
+        currency: &str,
+        date: NaiveDate,
+    ) -> Result<Price> {
+        let coin_id = self.get_coin_id(security_symbol);
+        let body = self.get_json(&self.history_url(&coin_id, date)).await?;
+
+        map_historical_price(&body, &coin_id, currency, date)
+    }
+}
+
+/// Reads the price from the simple/price response,
+/// i.e. {"bitcoin": {"eur": 39830.12, "last_updated_at": 1706718543}}
+fn map_latest_price(body: &Value, coin_id: &str, currency: &str) -> Result<Price> {
+    let coin = &body[coin_id];
+    if coin.is_null() {
+        return Err(anyhow!(
+            "No CoinGecko price for {coin_id}. Add the coin id to coingecko_coins in the configuration."
+        ));
+    }
+
+    let vs_currency = currency.to_lowercase();
+    let value = &coin[vs_currency.as_str()];
+    if value.is_null() {
+        return Err(anyhow!("No CoinGecko price for {coin_id} in {currency}"));
+    }
+
+    let seconds = coin["last_updated_at"]
+        .as_i64()
+        .ok_or_else(|| anyhow!("No last_updated_at in the CoinGecko response"))?;
+    let datetime = DateTime::from_timestamp(seconds, 0)
+        .ok_or_else(|| anyhow!("Invalid last_updated_at {seconds}"))?;
+
+    Ok(Price {
+        datetime: datetime.naive_utc(),
+        offset: Some(Utc.fix()),
+        value: parse_decimal(value)?,
+        currency: currency.to_uppercase(),
+        ..Price::default()
+    })
+}
+
+/// Reads the price from the coins/{id}/history response. The price is as of 00:00 UTC.
+fn map_historical_price(body: &Value, coin_id: &str, currency: &str, date: NaiveDate) -> Result<Price> {
+    let vs_currency = currency.to_lowercase();
+    let value = &body["market_data"]["current_price"][vs_currency.as_str()];
+    if value.is_null() {
+        return Err(anyhow!("No CoinGecko price for {coin_id} in {currency} on {date}"));
+    }
+
+    Ok(Price {
+        datetime: date.and_time(Default::default()),
+        offset: Some(Utc.fix()),
+        value: parse_decimal(value)?,
+        currency: currency.to_uppercase(),
+        ..Price::default()
+    })
+}
+
+fn get_error(body: &Value) -> String {
+    match (&body["error"], &body["status"]["error_message"]) {
+        (Value::String(error), _) => error.to_owned(),
+        (_, Value::String(error)) => error.to_owned(),
+        _ => body.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coin_ids() {
+        let mut dl = CoinGeckoDownloader::new();
+        dl.add_coins(&HashMap::from([("atom".to_string(), "cosmos".to_string())]));
+
+        assert_eq!("bitcoin", dl.get_coin_id(&SecuritySymbol::new("CRYPTO:BTC")));
+        assert_eq!("cosmos", dl.get_coin_id(&SecuritySymbol::new("CRYPTO:ATOM")));
+        // i.e. the updater_symbol
+        assert_eq!("usd-coin", dl.get_coin_id(&SecuritySymbol::new("CRYPTO:USD-COIN")));
+    }
+
+    #[test]
+    fn test_urls() {
+        let dl = CoinGeckoDownloader::new();
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+
+        assert_eq!(
+            "https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies=eur&include_last_updated_at=true",
+            dl.latest_url("bitcoin", "EUR")
+        );
+        assert_eq!(
+            "https://api.coingecko.com/api/v3/coins/bitcoin/history?date=31-01-2024&localization=false",
+            dl.history_url("bitcoin", date)
+        );
+    }
+
+    #[test]
+    fn test_latest_price() {
+        let body: Value = serde_json::from_str(
+            r#"{"bitcoin": {"eur": 39830.12, "last_updated_at": 1706718543}}"#,
+        )
+        .unwrap();
+
+        let actual = map_latest_price(&body, "bitcoin", "EUR").unwrap();
+
+        assert_eq!("39830.12", actual.value.to_string());
+        assert_eq!("EUR", actual.currency);
+        assert_eq!("2024-01-31 16:29:03", actual.datetime.to_string());
+        assert!(map_latest_price(&body, "bitcoin", "AUD").is_err());
+        assert!(map_latest_price(&body, "btc", "EUR").is_err());
+    }
+
+    #[test]
+    fn test_historical_price() {
+        let body: Value = serde_json::from_str(
+            r#"{"id": "ethereum", "symbol": "eth", "name": "Ethereum",
+                "market_data": {"current_price": {"eur": 2105.4486, "usd": 2283.32},
+                "market_cap": {"eur": 253075000000.0}}}"#,
+        )
+        .unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+
+        let actual = map_historical_price(&body, "ethereum", "usd", date).unwrap();
+
+        assert_eq!("2283.32", actual.value.to_string());
+        assert_eq!("USD", actual.currency);
+        assert_eq!("2024-01-31 00:00:00", actual.datetime.to_string());
+    }
+}
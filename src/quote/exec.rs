@@ -0,0 +1,238 @@
+/*!
+ * External command provider, for prices from custom scripts.
+ *
+ * Runs the command configured in the `[exec]` section. The security is passed in the
+ * environment variables PRICEDB_SYMBOL, PRICEDB_NAMESPACE, PRICEDB_CURRENCY, and,
+ * for the historical prices, PRICEDB_DATE. The same values can be used in the
+ * arguments as {symbol}, {namespace}, {currency}, and {date}.
+ *
+ * The command writes the price to stdout, either as a JSON object
+ * {"value": "12.34", "currency": "EUR", "date": "2024-01-31", "time": "17:35:00"},
+ * where only the value is required, or as a Ledger price line
+ * P 2024-01-31 17:35:00 SYMBOL 12.34 EUR
+ */
+use std::{process::Stdio, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::{FixedOffset, Local, NaiveDate, NaiveTime};
+use serde_json::Value;
+use tokio::process::Command;
+
+use crate::{
+    config::ExecConfig,
+    model::{Price, SecuritySymbol},
+    price_flat_file::PriceReader,
+};
+
+use super::{parse_decimal, Downloader};
+
+pub struct ExecDownloader {
+    config: ExecConfig,
+}
+
+impl ExecDownloader {
+    pub fn new(config: &ExecConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    async fn run(
+        &self,
+        security_symbol: &SecuritySymbol,
+        currency: &str,
+        date: Option<NaiveDate>,
+    ) -> Result<Price> {
+        if self.config.command.is_empty() {
+            return Err(anyhow!("The exec agent requires the command in the [exec] configuration section"));
+        }
+
+        let date_str = date.map(|date| date.to_string()).unwrap_or_default();
+        let args: Vec<String> = self
+            .config
+            .args
+            .iter()
+            .map(|arg| {
+                arg.replace("{symbol}", &security_symbol.mnemonic)
+                    .replace("{namespace}", &security_symbol.namespace)
+                    .replace("{currency}", currency)
+                    .replace("{date}", &date_str)
+            })
+            .collect();
+
+        log::debug!("running {} {:?}", self.config.command, args);
+
+        let child = Command::new(&self.config.command)
+            .args(&args)
+            .env("PRICEDB_SYMBOL", &security_symbol.mnemonic)
+            .env("PRICEDB_NAMESPACE", &security_symbol.namespace)
+            .env("PRICEDB_CURRENCY", currency)
+            .env("PRICEDB_DATE", &date_str)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Could not run {}", self.config.command))?;
+
+        let timeout = Duration::from_secs(self.config.timeout);
+        let output = tokio::time::timeout(timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "{} did not finish in {}s for {security_symbol}",
+                    self.config.command,
+                    timeout.as_secs()
+                )
+            })??;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} failed for {security_symbol} ({}): {}",
+                self.config.command,
+                output.status,
+                stderr.trim()
+            ));
+        }
+        if !stderr.trim().is_empty() {
+            log::warn!("{}: {}", self.config.command, stderr.trim());
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        parse_output(&stdout, currency, date)
+            .with_context(|| format!("Invalid output of {} for {security_symbol}", self.config.command))
+    }
+}
+
+#[async_trait]
+impl Downloader for ExecDownloader {
+    async fn download(&self, security_symbol: &SecuritySymbol, currency: &str) -> Result<Price> {
+        self.run(security_symbol, currency, None).await
+    }
+
+    fn supports_historical(&self) -> bool {
+        true
+    }
+
+    async fn download_historical(
+        &self,
+        security_symbol: &SecuritySymbol,
+        currency: &str,
+        date: NaiveDate,
+    ) -> Result<Price> {
+        self.run(security_symbol, currency, Some(date)).await
+    }
+}
+
+/// Parses the JSON object or the (last) Ledger price line.
+/// The currency and the date default to the requested ones.
+fn parse_output(output: &str, currency: &str, date: Option<NaiveDate>) -> Result<Price> {
+    let output = output.trim();
+    let default_date = date.unwrap_or_else(|| Local::now().date_naive());
+
+    if output.starts_with('{') {
+        let json: Value = serde_json::from_str(output)?;
+        let text = |name: &str| json[name].as_str().filter(|text| !text.is_empty());
+
+        let date = match text("date") {
+            Some(date) => NaiveDate::parse_from_str(date, "%Y-%m-%d")?,
+            None => default_date,
+        };
+        let time = match text("time") {
+            Some(time) => NaiveTime::parse_from_str(time, "%H:%M:%S")?,
+            None => NaiveTime::default(),
+        };
+        let offset = match text("offset") {
+            Some(offset) => Some(offset.parse::<FixedOffset>()?),
+            None => None,
+        };
+
+        return Ok(Price {
+            datetime: date.and_time(time),
+            offset,
+            value: parse_decimal(&json["value"]).context("Invalid value")?,
+            currency: text("currency").unwrap_or(currency).to_uppercase(),
+            ..Price::default()
+        });
+    }
+
+    let record = PriceReader::new(output.as_bytes())
+        .last()
+        .ok_or_else(|| anyhow!("No price in the output"))??;
+
+    Ok(Price {
+        datetime: record.datetime,
+        value: record.value,
+        currency: record.currency,
+        ..Price::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_json() {
+        let output = r#"{"value": 1234.5678, "date": "2024-01-31", "time": "17:35:00", "offset": "+01:00"}"#;
+
+        let actual = parse_output(output, "EUR", None).unwrap();
+
+        assert_eq!("1234.5678", actual.value.to_string());
+        assert_eq!("EUR", actual.currency);
+        assert_eq!("2024-01-31 17:35:00", actual.datetime.to_string());
+        assert_eq!(FixedOffset::east_opt(3600), actual.offset);
+    }
+
+    #[test]
+    fn test_parse_ledger_line() {
+        let output = "P 2024-01-31 PROPERTY 350000.00 AUD\n";
+        let date = NaiveDate::from_ymd_opt(2024, 2, 1);
+
+        let actual = parse_output(output, "EUR", date).unwrap();
+
+        assert_eq!("350000.00", actual.value.to_string());
+        assert_eq!("AUD", actual.currency);
+        assert_eq!("2024-01-31 00:00:00", actual.datetime.to_string());
+        assert!(parse_output("", "EUR", None).is_err());
+        assert!(parse_output(r#"{"currency": "EUR"}"#, "EUR", None).is_err());
+    }
+
+    #[cfg(unix)]
+    fn shell(script: &str, timeout: u64) -> ExecDownloader {
+        ExecDownloader::new(&ExecConfig {
+            command: "sh".into(),
+            args: vec!["-c".into(), script.into(), "{symbol}".into()],
+            timeout,
+        })
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_command() {
+        let dl = shell(r#"echo "P $PRICEDB_DATE $0 1.5 $PRICEDB_CURRENCY""#, 10);
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+
+        let actual = dl
+            .download_historical(&SecuritySymbol::new("INTERNAL:FUND"), "EUR", date)
+            .await
+            .unwrap();
+
+        assert_eq!("1.5", actual.value.to_string());
+        assert_eq!("2024-01-31 00:00:00", actual.datetime.to_string());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_failure_and_timeout() {
+        let symbol = SecuritySymbol::new("INTERNAL:FUND");
+
+        let failed = shell("echo 'no valuation' >&2; exit 3", 10).download(&symbol, "EUR").await;
+        let timed_out = shell("sleep 5", 1).download(&symbol, "EUR").await;
+
+        assert!(failed.unwrap_err().to_string().contains("no valuation"));
+        assert!(timed_out.unwrap_err().to_string().contains("did not finish"));
+    }
+}
@@ -1,45 +1,64 @@
-use std::{env::temp_dir, fs, path::Path, str::FromStr};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
-use confy::ConfyError;
-use rust_decimal::{
-    prelude::{FromPrimitive, ToPrimitive},
-    Decimal,
-};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, Offset};
+use chrono_tz::{Europe::Berlin, Tz};
+use rust_decimal::Decimal;
 use serde_json::Value;
 
 /// Fixerio downloader
 use crate::{
+    cache::{self, CacheMode},
     config::PriceDbConfig,
-    model::{SecuritySymbol, Price}, APP_NAME,
+    model::{SecuritySymbol, Price},
 };
 
-use super::Downloader;
+use super::{parse_decimal, Downloader};
+
+/// The time zone of the rates' source, the European Central Bank.
+const SOURCE_TIME_ZONE: Tz = Berlin;
 
 pub struct Fixerio {
     api_key: String,
+    cache_dir: PathBuf,
+    cache_expiry: Duration,
+    cache_mode: CacheMode,
 }
 
 impl Fixerio {
-    pub fn new() -> Self {
+    pub fn new(config: &PriceDbConfig) -> Self {
         Self {
-            api_key: get_fixerio_api_key(),
+            api_key: config.fixerio_api_key.to_owned(),
+            cache_dir: cache::get_cache_dir(config),
+            cache_expiry: cache::get_provider_expiry(config, "fixerio"),
+            cache_mode: CacheMode::default(),
         }
     }
 
-    /// Saves the retrieved rates into a cache file.
-    fn cache_rates(&self, rates: &Value) {
-        let file_date = rates["date"].as_str().unwrap();
-        let file_path = get_rate_file_path(file_date);
+    pub fn set_cache_mode(&mut self, cache_mode: CacheMode) {
+        self.cache_mode = cache_mode;
+    }
 
-        let content = rates.to_string();
+    /// Saves the retrieved rates into a cache file, for the base currency and the
+    /// date of the request.
+    fn cache_rates(&self, base_currency: &str, rates: &Value) -> Result<()> {
+        let file_path = self.get_todays_file_path(base_currency);
 
-        let path = Path::new(&file_path);
-        match fs::write(path, content) {
-            Ok(_) => (),
-            Err(_) => panic!("Could not cache rates"),
-        }
+        cache::write(&file_path, &rates.to_string())
+    }
+
+    /// Reads today's rates in the base currency from the cache, if not expired.
+    fn read_cached_rates(&self, base_currency: &str) -> Option<Value> {
+        let file_path = self.get_todays_file_path(base_currency);
+
+        log::debug!("Loading rates from {}", file_path.display());
+
+        let content = cache::read(&file_path, self.cache_expiry)?;
+        serde_json::from_str(&content).ok()
     }
 
     /// Downloads the latest rates. Requires base currency and a list of currencies to
@@ -55,16 +74,60 @@ impl Fixerio {
             .await?
             .json()
             .await
-            .expect("Error retrieving quotes");
+            .context("Error retrieving quotes")?;
+
+        if result["success"] == Value::Bool(false) {
+            return Err(anyhow!("Fixer error: {}", result["error"]));
+        }
 
         Ok(result)
     }
 
-    fn latest_rates_exist(&self) -> bool {
-        let file_path = get_todays_file_path();
+    /// Derives the cross rates for the given (commodity, currency) pairs from the
+    /// rates in the base currency, i.e. AUD/USD from the EUR-based rates.
+    /// Uses a single (cached) response for all the pairs.
+    pub async fn cross_rates(
+        &self,
+        base_currency: &str,
+        pairs: &[(String, String)],
+    ) -> Result<Vec<Price>> {
+        let rates_json = self.get_rates(&base_currency.to_uppercase()).await?;
+
+        pairs
+            .iter()
+            .map(|(commodity, currency)| map_cross_rate(&rates_json, commodity, currency))
+            .collect()
+    }
+
+    /// Reads the rates from the cache, or downloads them.
+    async fn get_rates(&self, base_currency: &str) -> Result<Value> {
+        if self.cache_mode.reads() {
+            if let Some(rates_json) = self.read_cached_rates(base_currency) {
+                log::debug!("Read cached rates");
+                return Ok(rates_json);
+            }
+        }
+
+        let rates_json = self.download_rates(base_currency).await?;
+
+        if self.cache_mode.writes() {
+            self.cache_rates(base_currency, &rates_json)?;
+        }
+
+        Ok(rates_json)
+    }
+
+    /// Assemble the full file path for the base currency and the date of the request.
+    fn get_rate_file_path(&self, base_currency: &str, date: NaiveDate) -> PathBuf {
+        let filename = format!("fixerio_{base_currency}_{}.json", date.format("%Y-%m-%d"));
+
+        Path::new(&self.cache_dir).join(filename)
+    }
+
+    fn get_todays_file_path(&self, base_currency: &str) -> PathBuf {
+        let today = chrono::offset::Local::now().date_naive();
 
-        let exists = std::path::Path::new(&file_path).exists();
-        exists
+        self.get_rate_file_path(base_currency, today)
     }
 }
 
@@ -72,113 +135,145 @@ impl Fixerio {
 impl Downloader for Fixerio {
     /// Download latest rates. Caches the (daily) prices into a temp directory.
     async fn download(&self, security_symbol: &SecuritySymbol, currency: &str) -> Result<Price> {
-        //let namespace = security_symbol.namespace.to_uppercase();
         let currency = currency.to_uppercase();
-        let mnemonic = security_symbol.mnemonic.to_uppercase();
+        let mnemonic = get_currency_symbol(security_symbol)?;
 
-        if mnemonic.contains(':') {
-            panic!("Currency symbol should not contain namespace.");
-        }
-
-        let rates_json: Value;
-        if self.latest_rates_exist() {
-            log::debug!("Reading cached rates");
-            rates_json = read_rates_from_cache();
-
-            // log::debug!("Read rates from the cache file: {:?}", rates_json);
-        } else {
-            rates_json = self
-                .download_rates(&currency)
-                .await
-                .expect("Error downloading rates");
-
-            self.cache_rates(&rates_json);
-        }
+        let rates_json = self.get_rates(&currency).await?;
 
         log::debug!("Mapping rates for {}", &mnemonic);
-        let rate = map_rates_to_price(rates_json, &mnemonic);
-
-        Ok(rate)
+        map_rates_to_price(&rates_json, &mnemonic)
     }
-}
 
-/// Loads Fixerio API key from the config.
-/// Panics if not found.
-fn get_fixerio_api_key() -> String {
-    let config_result: Result<PriceDbConfig, ConfyError> = confy::load(APP_NAME, APP_NAME);
-    match config_result {
-        Ok(config) => config.fixerio_api_key,
-        Err(e) => panic!("Fixerio API key not loaded: {}", e),
+    /// All the rates in the currency come from the same response.
+    async fn download_batch(
+        &self,
+        symbols: &[SecuritySymbol],
+        currency: &str,
+    ) -> Vec<Result<Price>> {
+        let rates_json = match self.get_rates(&currency.to_uppercase()).await {
+            Ok(rates_json) => rates_json,
+            Err(error) => {
+                return symbols
+                    .iter()
+                    .map(|_| Err(anyhow!("Error retrieving the {currency} rates: {error:#}")))
+                    .collect()
+            }
+        };
+
+        symbols
+            .iter()
+            .map(|symbol| map_rates_to_price(&rates_json, &get_currency_symbol(symbol)?))
+            .collect()
     }
 }
 
-fn get_cache_path() -> String {
-    temp_dir().into_os_string().into_string().expect("Error")
-}
-
-/// Assemble the full file path for the given name (date).
-fn get_rate_file_path(today_iso_str: &str) -> String {
-    let cache_path = get_cache_path();
-    let filename = today_iso_str;
-    // todo: check the separators on Linux. On windows, it is double.
-    format!("{cache_path}{}fixerio_{filename}.json", std::path::MAIN_SEPARATOR)
-}
+/// The currency code. The currency symbols have no namespace.
+fn get_currency_symbol(security_symbol: &SecuritySymbol) -> Result<String> {
+    let mnemonic = security_symbol.mnemonic.to_uppercase();
 
-fn get_todays_file_path() -> String {
-    let today = chrono::offset::Local::now();
-    let today_str = today.date_naive().format("%Y-%m-%d").to_string();
+    if mnemonic.contains(':') {
+        return Err(anyhow!("Currency symbol should not contain namespace: {mnemonic}"));
+    }
 
-    get_rate_file_path(&today_str)
+    Ok(mnemonic)
 }
 
 /// Read and map a single currency rate
 /// symbol: The currency to fetch the rate for.
-fn map_rates_to_price(rates: Value, symbol: &str) -> Price {
-    let date_str = rates["date"].as_str().unwrap().to_string();
+fn map_rates_to_price(rates: &Value, symbol: &str) -> Result<Price> {
+    let (datetime, offset) = get_datetime(rates)?;
 
     // Get value
 
-    let base = rates["base"].as_str().unwrap().to_string();
+    let base = get_base(rates)?.to_string();
     let rates_dict = &rates["rates"];
     let rate_node = &rates_dict[symbol];
     
     log::debug!("Rate located: {:?}", rate_node);
 
-    let value_f = rate_node.as_f64().unwrap();
-    let value = Decimal::from_f64(value_f).expect("Error parsing value");
+    if rate_node.is_null() {
+        return Err(anyhow!("No {base} rate for {symbol} in the Fixer response"));
+    }
+    let value = parse_decimal(rate_node).with_context(|| format!("Invalid rate for {symbol}"))?;
     // The rate is inverse value.
-    let rate = Decimal::ONE / value;
+    let rate = Decimal::ONE
+        .checked_div(value)
+        .ok_or_else(|| anyhow!("Invalid {base} rate {value} for {symbol}"))?;
     
     log::debug!("The inverse rate is {:?}", rate);
 
-    // Round to 6 decimals max.
-    let rounded_str = format!("{0:.6}", rate);
-    let rounded = Decimal::from_str(&rounded_str).unwrap();
-    
-    log::debug!("Rounded rate: {rounded:?}");
-
     // result
 
-    Price {
+    Ok(Price {
         symbol: String::default(),
         id: i64::default(),
-        date: date_str,
-        time: Price::default_time(),
-        value: rounded.mantissa().to_i64().unwrap(),
-        denom: 10_i64.pow(rounded.scale()),         // in 10^3 = 1000, scale=3, denom=1000
+        datetime,
+        offset,
+        value: rate,
         currency: base,
+    })
+}
+
+/// The base currency of the rates.
+fn get_base(rates: &Value) -> Result<&str> {
+    rates["base"]
+        .as_str()
+        .ok_or_else(|| anyhow!("No base currency in the Fixer response"))
+}
+
+/// The time of the rates, from the response's `timestamp`, in the source's (ECB)
+/// Central European Time. Only the date, if there is no timestamp.
+fn get_datetime(rates: &Value) -> Result<(NaiveDateTime, Option<FixedOffset>)> {
+    if let Some(seconds) = rates["timestamp"].as_i64() {
+        let datetime = DateTime::from_timestamp(seconds, 0)
+            .ok_or_else(|| anyhow!("Invalid timestamp {seconds}"))?
+            .with_timezone(&SOURCE_TIME_ZONE);
+
+        return Ok((datetime.naive_local(), Some(datetime.offset().fix())));
     }
+
+    let date_str = rates["date"]
+        .as_str()
+        .ok_or_else(|| anyhow!("No date in the Fixer response"))?;
+    let date = NaiveDate::parse_from_str(date_str, "%Y-%m-%d")?;
+
+    Ok((date.and_time(Default::default()), None))
 }
 
-fn read_rates_from_cache() -> Value {
-    let file_path = get_todays_file_path();
+/// Calculates the price of the commodity currency in the quote currency,
+/// from the rates in a third (base) currency.
+fn map_cross_rate(rates: &Value, commodity: &str, currency: &str) -> Result<Price> {
+    let (datetime, offset) = get_datetime(rates)?;
+    let base = get_base(rates)?;
+
+    // Units of the currency for one unit of the base.
+    let base_rate = |symbol: &str| -> Result<Decimal> {
+        if symbol == base {
+            return Ok(Decimal::ONE);
+        }
+        let rate = &rates["rates"][symbol];
+        if rate.is_null() {
+            return Err(anyhow!("No {base} rate for {symbol} in the Fixer response"));
+        }
+        parse_decimal(rate)
+    };
 
-    log::debug!("Loading rates from {}", file_path);
+    let commodity_rate = base_rate(commodity)?;
+    let currency_rate = base_rate(currency)?;
+    let rate = currency_rate
+        .checked_div(commodity_rate)
+        .ok_or_else(|| anyhow!("Invalid {base} rate {commodity_rate} for {commodity}"))?;
 
-    let content = fs::read_to_string(file_path).expect("Error reading rates file");
+    log::debug!("Cross rate {commodity}/{currency}: {rate}");
 
-    serde_json::from_str(&content)
-        .expect("Error parsing rates JSON")
+    Ok(Price {
+        symbol: commodity.to_owned(),
+        id: i64::default(),
+        datetime,
+        offset,
+        value: rate,
+        currency: currency.to_owned(),
+    })
 }
 
 // Tests
@@ -203,7 +298,7 @@ mod tests {
     /// This test depends on having a value 
     #[test]
     fn test_config_read() {
-        let key = get_fixerio_api_key();
+        let key = crate::load_config().fixerio_api_key;
 
         assert_ne!(key, String::default());
         assert_eq!(key.len(), 32);
@@ -214,58 +309,145 @@ mod tests {
     // #[tokio::test]
     #[rstest::rstest]
     fn test_cache_check(fixerio_json: Value) {
-        let f = Fixerio::new();
+        let dir = tempfile::tempdir().unwrap();
+        let config = PriceDbConfig {
+            cache_path: dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let f = Fixerio::new(&config);
         //f.download_rates("EUR").await.expect("rates fetched");
-        f.cache_rates(&fixerio_json);
+        f.cache_rates("EUR", &fixerio_json).unwrap();
 
-        let result = f.latest_rates_exist();
+        assert_eq!(Some(fixerio_json), f.read_cached_rates("EUR"));
+        // the cache is per base currency
+        assert_eq!(None, f.read_cached_rates("USD"));
+    }
 
-        assert_eq!(true, result);
+    /// The batch uses the cached rates, without downloading.
+    #[tokio::test]
+    async fn test_download_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = PriceDbConfig {
+            cache_path: dir.path().to_string_lossy().to_string(),
+            ..Default::default()
+        };
+        let f = Fixerio::new(&config);
+        let rates = json!({"base": "EUR", "date": "2024-01-31", "rates": {"AUD": 1.6459, "GBP": 0.85}});
+        f.cache_rates("EUR", &rates).unwrap();
+        let symbols = [SecuritySymbol::new("AUD"), SecuritySymbol::new("GBP")];
+
+        let actual = f.download_batch(&symbols, "eur").await;
+
+        assert_eq!("0.607570", actual[0].as_ref().unwrap().value.round_dp(6).to_string());
+        assert_eq!("1.176471", actual[1].as_ref().unwrap().value.round_dp(6).to_string());
+
+        // A missing currency or a namespace fails only that symbol.
+        let symbols = [
+            SecuritySymbol::new("AUD"),
+            SecuritySymbol::new("USD"),
+            SecuritySymbol::new_separated("", "CURRENCY:GBP"),
+        ];
+        let actual = f.download_batch(&symbols, "eur").await;
+
+        assert!(actual[0].is_ok());
+        assert!(actual[1].is_err());
+        assert!(actual[2].is_err());
     }
 
     #[test]
     fn test_cache_location() {
         let year = Local::now().date_naive().format("%Y").to_string();
         // println!("year: {:?}", year);
-        let result = get_todays_file_path();
+        let f = Fixerio::new(&PriceDbConfig::default());
+        let result = f.get_todays_file_path("EUR");
 
         println!("Fixerio cache file: {result:?}");
 
-        assert_ne!(result, String::default());
-        // on linux: /tmp/fixerio_2022-12-06.json
-        //assert_eq!(28, result.len());
-        assert!(result.contains(&year));
+        // on linux: /tmp/pricedb/fixerio_EUR_2022-12-06.json
+        let name = result.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("fixerio_EUR_"));
+        assert!(name.contains(&year));
+    }
+
+    #[test]
+    fn test_map_rates_to_price() {
+        let rates: Value = serde_json::from_str(
+            r#"{"base": "EUR", "date": "2024-01-31", "rates": {"AUD": 1.6459, "GBP": 0.85}}"#,
+        )
+        .unwrap();
+
+        let actual = map_rates_to_price(&rates, "AUD").unwrap();
+
+        assert_eq!("EUR", actual.currency);
+        assert_eq!("2024-01-31 00:00:00", actual.datetime.to_string());
+        assert_eq!(None, actual.offset);
+        assert_eq!("0.607570", actual.value.round_dp(6).to_string());
+    }
+
+    #[test]
+    fn test_rates_timestamp() {
+        let rates: Value = serde_json::from_str(
+            r#"{"base": "EUR", "date": "2024-01-31", "timestamp": 1706718543, "rates": {"AUD": 1.6}}"#,
+        )
+        .unwrap();
+
+        let actual = map_cross_rate(&rates, "EUR", "AUD").unwrap();
+
+        assert_eq!("2024-01-31 17:29:03", actual.datetime.to_string());
+        assert_eq!(FixedOffset::east_opt(3600), actual.offset);
+    }
+
+    #[test]
+    fn test_cross_rate() {
+        let rates: Value = serde_json::from_str(
+            r#"{"base": "EUR", "date": "2024-01-31", "rates": {"AUD": 1.6, "USD": 1.08}}"#,
+        )
+        .unwrap();
+
+        let aud_usd = map_cross_rate(&rates, "AUD", "USD").unwrap();
+        let eur_aud = map_cross_rate(&rates, "EUR", "AUD").unwrap();
+
+        assert_eq!("AUD", aud_usd.symbol);
+        assert_eq!("USD", aud_usd.currency);
+        assert_eq!("0.675", aud_usd.value.to_string());
+        assert_eq!("1.6", eur_aud.value.to_string());
+        assert!(map_cross_rate(&rates, "AUD", "JPY").is_err());
+
+        let zero = json!({"base": "EUR", "date": "2024-01-31", "rates": {"AUD": 0, "USD": 1.08}});
+        assert!(map_cross_rate(&zero, "AUD", "USD").is_err());
+        assert!(map_rates_to_price(&zero, "AUD").is_err());
+
+        // an error response
+        let error: Value = serde_json::from_str(
+            r#"{"success": false, "date": "2024-01-31", "error": {"code": 101}}"#,
+        )
+        .unwrap();
+        assert!(map_cross_rate(&error, "AUD", "USD").is_err());
     }
 
     #[test_log::test(tokio::test)]
     async fn test_price_parsing_aud() {
         let symbol = SecuritySymbol::new("CURRENCY:AUD");
 
-        let f = Fixerio::new();
+        let f = Fixerio::new(&crate::load_config());
         let price = f.download(&symbol, "EUR").await.expect("Error");
 
-        let value = price.to_decimal();
-        
         println!("Parsing AUDEUR rate...");
         println!("parsed price: {price:?}");
-        println!("price value: {value:?}");
 
-        assert!(price.value > 0);
+        assert!(price.value > Decimal::ZERO);
     }
 
     #[test_log::test(tokio::test)]
     async fn test_price_parsing_gbp() {
         let symbol = SecuritySymbol::new("CURRENCY:GBP");
 
-        let f = Fixerio::new();
+        let f = Fixerio::new(&crate::load_config());
         let price = f.download(&symbol, "EUR").await.expect("Error");
 
-        let value = price.to_decimal();
-        
         println!("Parsing GBPEUR rate...");
         println!("parsed price: {price:?}");
-        println!("price value: {value:?}");
 
-        assert!(price.value > 0);
+        assert!(price.value > Decimal::ZERO);
     }
 }
@@ -0,0 +1,232 @@
+/*!
+ * Prices scraped from HTML pages, for the funds that publish the NAV only on a web page.
+ *
+ * Each symbol has its page in the `[html_pages]` configuration section, with the CSS
+ * selectors for the price and the date elements. I.e.
+ *
+ * [html_pages."VANGUARD:HY"]
+ * url = "https://www.example.com.au/funds/high-yield/prices"
+ * price = "table.unit-prices td.nav"
+ * date = "p.as-of"
+ * date_format = "%d %b %Y"
+ *
+ * The price is the first number in the element's text, so currency prefixes and
+ * suffixes ($1.0123, 1.234,56 EUR) are skipped. For the decimal commas, set
+ * `decimal_separator = ","`. The date is searched for in the text with the format.
+ */
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{Local, NaiveDate};
+use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
+use rust_decimal::Decimal;
+use scraper::{Html, Selector};
+
+use crate::{
+    config::HtmlPageConfig,
+    model::{Price, SecuritySymbol},
+};
+
+use super::Downloader;
+
+pub struct HtmlPageDownloader {
+    pages: HashMap<String, HtmlPageConfig>,
+}
+
+impl HtmlPageDownloader {
+    pub fn new() -> Self {
+        Self {
+            pages: HashMap::new(),
+        }
+    }
+
+    /// Adds the pages, per symbol.
+    pub fn add_pages(&mut self, pages: &HashMap<String, HtmlPageConfig>) {
+        for (symbol, page) in pages {
+            self.pages.insert(symbol.to_uppercase(), page.clone());
+        }
+    }
+
+    fn get_page(&self, symbol: &SecuritySymbol) -> Result<&HtmlPageConfig> {
+        let sec_symbol = symbol.to_string();
+        self.pages.get(&sec_symbol).ok_or_else(|| {
+            anyhow!("No page for {sec_symbol}. Set it in html_pages in the configuration.")
+        })
+    }
+
+    async fn get_html(&self, url: &str) -> Result<String> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static("pricedb"));
+
+        log::debug!("fetching from {:?}", url);
+
+        let response = reqwest::Client::new().get(url).headers(headers).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Error {} from {url}", response.status()));
+        }
+
+        Ok(response.text().await?)
+    }
+}
+
+#[async_trait]
+impl Downloader for HtmlPageDownloader {
+    async fn download(&self, security_symbol: &SecuritySymbol, currency: &str) -> Result<Price> {
+        let page = self.get_page(security_symbol)?;
+        let content = self.get_html(&page.url).await?;
+
+        parse_page(&content, page, currency)
+            .with_context(|| format!("No price for {security_symbol} at {}", page.url))
+    }
+}
+
+/// Reads the price, and the date, from the page.
+fn parse_page(content: &str, page: &HtmlPageConfig, currency: &str) -> Result<Price> {
+    let document = Html::parse_document(content);
+
+    let select = |selector: &str| -> Result<String> {
+        let parsed = Selector::parse(selector).map_err(|e| anyhow!("Invalid selector {selector}: {e}"))?;
+        let element = document
+            .select(&parsed)
+            .next()
+            .ok_or_else(|| anyhow!("Nothing matches {selector}"))?;
+
+        Ok(element.text().collect::<String>().trim().to_owned())
+    };
+
+    let value = parse_number(&select(&page.price)?, &page.decimal_separator)?;
+
+    let date = match &page.date {
+        Some(selector) => find_date(&select(selector)?, &page.date_format)?,
+        None => Local::now().date_naive(),
+    };
+
+    let currency = match &page.currency {
+        Some(currency) => currency,
+        None => currency,
+    };
+
+    Ok(Price {
+        datetime: date.and_time(Default::default()),
+        value,
+        currency: currency.to_uppercase(),
+        ..Price::default()
+    })
+}
+
+/// Reads the first number in the text, i.e. "$1,234.56" or "1.234,56 EUR" (with the
+/// decimal comma). The grouping separators are removed, and must be followed by
+/// three digits, so that "1,5" is not read as 15 with the decimal point.
+fn parse_number(text: &str, decimal_separator: &str) -> Result<Decimal> {
+    let decimal = decimal_separator.chars().next().unwrap_or('.');
+    let is_part = |c: char| c.is_ascii_digit() || matches!(c, '.' | ',' | '\'' | '\u{a0}' | '\u{202f}');
+
+    let start = text
+        .find(|c: char| c.is_ascii_digit())
+        .ok_or_else(|| anyhow!("No number in {text:?}"))?;
+    let negative = text[..start].trim_end().ends_with('-');
+    let part: Vec<char> = text[start..].chars().take_while(|c| is_part(*c)).collect();
+
+    for (index, c) in part.iter().enumerate() {
+        if c.is_ascii_digit() || *c == decimal {
+            continue;
+        }
+        let digits = part[index + 1..].iter().take_while(|c| c.is_ascii_digit()).count();
+        // the spaces may also end the number, i.e. "1.234,56 EUR"
+        if digits != 3 && (digits > 0 || matches!(c, '.' | ',')) {
+            bail!("Invalid number {text:?} with the decimal separator {decimal:?}");
+        }
+    }
+
+    let number: String = part
+        .into_iter()
+        .filter_map(|c| match c {
+            c if c.is_ascii_digit() => Some(c),
+            c if c == decimal => Some('.'),
+            _ => None,
+        })
+        .collect();
+
+    let value = Decimal::from_str_exact(number.trim_end_matches('.'))
+        .with_context(|| format!("Invalid number {text:?}"))?;
+
+    Ok(if negative { -value } else { value })
+}
+
+/// Finds the date in the text, i.e. "Prices as at 31 Jan 2024".
+fn find_date(text: &str, format: &str) -> Result<NaiveDate> {
+    text.char_indices()
+        .find_map(|(index, _)| NaiveDate::parse_and_remainder(&text[index..], format).ok())
+        .map(|(date, _)| date)
+        .ok_or_else(|| anyhow!("No date as {format} in {text:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn page(price: &str, date: &str, date_format: &str, decimal_separator: &str) -> HtmlPageConfig {
+        HtmlPageConfig {
+            url: "https://www.example.com/prices".into(),
+            price: price.into(),
+            date: Some(date.into()),
+            date_format: date_format.into(),
+            decimal_separator: decimal_separator.into(),
+            currency: None,
+        }
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(dec!(1.0123), parse_number("$1.0123", ".").unwrap());
+        assert_eq!(dec!(1234.56), parse_number("A$ 1,234.56", ".").unwrap());
+        assert_eq!(dec!(1234.56), parse_number("1.234,56\u{a0}€", ",").unwrap());
+        assert_eq!(dec!(-0.5), parse_number("Change: -0,50 %", ",").unwrap());
+        assert_eq!(dec!(12), parse_number("CHF 12.–", ".").unwrap());
+        assert!(parse_number("n/a", ".").is_err());
+        assert_eq!(dec!(1234567), parse_number("1'234'567", ".").unwrap());
+        assert!(parse_number("1,5", ".").is_err());
+        assert!(parse_number("1,2345.6", ".").is_err());
+        assert!(parse_number("1.5 EUR", ",").is_err());
+        assert!(parse_number("1\u{a0}23", ".").is_err());
+    }
+
+    #[test]
+    fn test_dates() {
+        let expected = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+
+        assert_eq!(expected, find_date("Prices as at 31 Jan 2024", "%d %b %Y").unwrap());
+        assert_eq!(expected, find_date("Stand: 31.01.2024", "%d.%m.%Y").unwrap());
+        assert!(find_date("Stand: heute", "%d.%m.%Y").is_err());
+    }
+
+    #[test]
+    fn test_parse_page() {
+        let content = include_str!("../../tests/html_page_au.html");
+        let page = page("table.unit-prices td.nav", "p.as-of", "%d %b %Y", ".");
+
+        let actual = parse_page(content, &page, "aud").unwrap();
+
+        assert_eq!(dec!(1.0123), actual.value);
+        assert_eq!("AUD", actual.currency);
+        assert_eq!("2024-01-31 00:00:00", actual.datetime.to_string());
+    }
+
+    #[test]
+    fn test_parse_page_decimal_comma() {
+        let content = include_str!("../../tests/html_page_de.html");
+        let mut page = page("dd#nav", "#date", "%d.%m.%Y", ",");
+        page.currency = Some("EUR".into());
+
+        let actual = parse_page(content, &page, "USD").unwrap();
+
+        assert_eq!(dec!(1234.56), actual.value);
+        assert_eq!("EUR", actual.currency);
+        assert_eq!("2024-01-31 00:00:00", actual.datetime.to_string());
+        page.price = "dd#missing".into();
+        assert!(parse_page(content, &page, "EUR").is_err());
+    }
+}
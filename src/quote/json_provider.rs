@@ -0,0 +1,304 @@
+/*!
+ * Generic JSON/HTTP provider, defined in the configuration.
+ *
+ * Each `[json_providers.<name>]` section defines an agent with the given name:
+ * the URL template, the optional headers, and the JSON pointers to the value, the
+ * date, and the currency in the response. I.e.
+ *
+ * [json_providers.vanguard_nav]
+ * url = "https://www.vanguard.com.au/personal/api/products/personal/fund/{symbol}/detail?limit=-1"
+ * value = "/data/0/navPrices/0/price"
+ * date = "/data/0/navPrices/0/asOfDate"
+ * currency = "/data/0/navPrices/0/currencyCode"
+ *
+ * The URL and the headers can contain {symbol}, {namespace}, {currency}, and {date}
+ * placeholders. The date is formatted as YYYY-MM-DD, or with the given format,
+ * i.e. {date:%d-%m-%Y}.
+ */
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, Offset, Utc,
+};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::Value;
+
+use crate::{
+    config::JsonProviderConfig,
+    model::{Price, SecuritySymbol},
+};
+
+use super::{parse_decimal, Downloader};
+
+pub struct JsonDownloader {
+    name: String,
+    config: JsonProviderConfig,
+}
+
+impl JsonDownloader {
+    pub fn new(name: &str, config: &JsonProviderConfig) -> Self {
+        Self {
+            name: name.to_owned(),
+            config: config.clone(),
+        }
+    }
+
+    async fn get_price(
+        &self,
+        security_symbol: &SecuritySymbol,
+        currency: &str,
+        date: NaiveDate,
+    ) -> Result<Price> {
+        let fill = |template: &str, in_url| {
+            fill_template(template, security_symbol, currency, date, in_url)
+        };
+
+        let url = fill(&self.config.url, true)?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.config.headers {
+            headers.insert(
+                HeaderName::try_from(name.as_str())?,
+                HeaderValue::try_from(fill(value, false)?)?,
+            );
+        }
+
+        log::debug!("fetching from {:?}", url);
+
+        let response = reqwest::Client::new().get(&url).headers(headers).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("{} error: {}", self.name, response.status()));
+        }
+        let body: Value = response.json().await?;
+
+        self.map_price(&body, currency, date)
+            .with_context(|| format!("Invalid {} response for {security_symbol}", self.name))
+    }
+
+    /// Reads the price from the response, using the configured JSON pointers.
+    /// Without the date pointer, the price is on the requested date.
+    fn map_price(&self, body: &Value, currency: &str, date: NaiveDate) -> Result<Price> {
+        let select = |pointer: &str| {
+            body.pointer(pointer)
+                .filter(|value| !value.is_null())
+                .ok_or_else(|| anyhow!("Nothing at {pointer}"))
+        };
+
+        let value = parse_decimal(select(&self.config.value)?)?;
+
+        let currency = match &self.config.currency {
+            Some(pointer) => select(pointer)?
+                .as_str()
+                .ok_or_else(|| anyhow!("The currency at {pointer} is not a text"))?
+                .to_uppercase(),
+            None => currency.to_uppercase(),
+        };
+
+        let (datetime, offset) = match &self.config.date {
+            Some(pointer) => parse_date(select(pointer)?, &self.config.date_format)?,
+            None => (date.and_time(Default::default()), None),
+        };
+
+        Ok(Price {
+            datetime,
+            offset,
+            value,
+            currency,
+            ..Price::default()
+        })
+    }
+}
+
+#[async_trait]
+impl Downloader for JsonDownloader {
+    async fn download(&self, security_symbol: &SecuritySymbol, currency: &str) -> Result<Price> {
+        let today = Local::now().date_naive();
+
+        self.get_price(security_symbol, currency, today).await
+    }
+
+    /// Only with the date in the url.
+    fn supports_historical(&self) -> bool {
+        self.config.url.contains("{date")
+    }
+
+    async fn download_historical(
+        &self,
+        security_symbol: &SecuritySymbol,
+        currency: &str,
+        date: NaiveDate,
+    ) -> Result<Price> {
+        if !self.supports_historical() {
+            return Err(anyhow!(
+                "Historical prices are not supported by {}. The url has no {{date}}.",
+                self.name
+            ));
+        }
+
+        self.get_price(security_symbol, currency, date).await
+    }
+}
+
+/// Replaces the placeholders in the template. The values are percent-encoded for
+/// the url, i.e. ^GSPC as %5EGSPC.
+fn fill_template(
+    template: &str,
+    symbol: &SecuritySymbol,
+    currency: &str,
+    date: NaiveDate,
+    in_url: bool,
+) -> Result<String> {
+    let value = |text: &str| if in_url { encode(text) } else { text.to_owned() };
+
+    let mut result = template
+        .replace("{symbol}", &value(&symbol.mnemonic))
+        .replace("{namespace}", &value(&symbol.namespace))
+        .replace("{currency}", &value(currency))
+        .replace("{date}", &value(&date.format("%Y-%m-%d").to_string()));
+
+    // {date:<format>}
+    while let Some(start) = result.find("{date:") {
+        let Some(length) = result[start..].find('}') else {
+            break;
+        };
+        let format = &result[start + 6..start + length];
+        if StrftimeItems::new(format).any(|item| item == Item::Error) {
+            return Err(anyhow!("Invalid date format {format} in {template}"));
+        }
+        let formatted = value(&date.format(format).to_string());
+        result.replace_range(start..=start + length, &formatted);
+    }
+
+    Ok(result)
+}
+
+/// Percent-encodes all but the unreserved characters of RFC 3986.
+fn encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Parses the date (and time) with the format. "timestamp" is for the Unix time in
+/// seconds. A format with %z gives the UTC offset.
+fn parse_date(value: &Value, format: &str) -> Result<(NaiveDateTime, Option<FixedOffset>)> {
+    if format == "timestamp" {
+        let seconds = match value {
+            Value::String(text) => text.parse()?,
+            _ => value.as_i64().ok_or_else(|| anyhow!("Invalid timestamp {value}"))?,
+        };
+        let datetime = DateTime::from_timestamp(seconds, 0)
+            .ok_or_else(|| anyhow!("Invalid timestamp {seconds}"))?;
+        return Ok((datetime.naive_utc(), Some(Utc.fix())));
+    }
+
+    let text = value
+        .as_str()
+        .ok_or_else(|| anyhow!("The date {value} is not a text"))?;
+
+    if format.contains("%z") || format.contains("%:z") {
+        let datetime = DateTime::parse_from_str(text, format)?;
+        return Ok((datetime.naive_local(), Some(*datetime.offset())));
+    }
+
+    match NaiveDateTime::parse_from_str(text, format) {
+        Ok(datetime) => Ok((datetime, None)),
+        Err(_) => {
+            let date = NaiveDate::parse_from_str(text, format)
+                .with_context(|| format!("The date {text} does not match {format}"))?;
+            Ok((date.and_time(Default::default()), None))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn vanguard() -> JsonDownloader {
+        let config = JsonProviderConfig {
+            url: "https://www.vanguard.com.au/personal/api/products/personal/fund/{symbol}/detail?limit=-1".into(),
+            headers: HashMap::new(),
+            value: "/data/0/navPrices/0/price".into(),
+            date: Some("/data/0/navPrices/0/asOfDate".into()),
+            date_format: "%Y-%m-%d".into(),
+            currency: Some("/data/0/navPrices/0/currencyCode".into()),
+        };
+
+        JsonDownloader::new("vanguard_nav", &config)
+    }
+
+    #[test]
+    fn test_fill_template() {
+        let symbol = SecuritySymbol::new("CRYPTO:BTC");
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+
+        let actual = fill_template(
+            "https://example.com/{namespace}/{symbol}?vs={currency}&on={date}&d={date:%d-%m-%Y}",
+            &symbol,
+            "EUR",
+            date,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!("https://example.com/CRYPTO/BTC?vs=EUR&on=2024-01-31&d=31-01-2024", actual);
+        assert!(fill_template("https://example.com/?d={date:%Q}", &symbol, "EUR", date, true).is_err());
+
+        // encoded in the url, not in the headers
+        let index = SecuritySymbol::new_separated("", "^GSPC&X=1 Y");
+        let template = "https://example.com/{symbol}?d={date:%d %b %Y}";
+        assert_eq!(
+            "https://example.com/%5EGSPC%26X%3D1%20Y?d=31%20Jan%202024",
+            fill_template(template, &index, "USD", date, true).unwrap()
+        );
+        assert_eq!("^GSPC&X=1 Y", fill_template("{symbol}", &index, "USD", date, false).unwrap());
+    }
+
+    #[test]
+    fn test_map_price() {
+        let body: Value = serde_json::from_str(
+            r#"{"data": [{"portId": "8106", "navPrices": [
+                {"asOfDate": "2024-01-30", "price": 1.0123, "currencyCode": "AUD"},
+                {"asOfDate": "2024-01-29", "price": 1.0101, "currencyCode": "AUD"}]}]}"#,
+        )
+        .unwrap();
+
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+
+        let actual = vanguard().map_price(&body, "EUR", date).unwrap();
+
+        assert_eq!("1.0123", actual.value.to_string());
+        assert_eq!("AUD", actual.currency);
+        assert_eq!("2024-01-30 00:00:00", actual.datetime.to_string());
+        assert!(vanguard().map_price(&Value::Null, "EUR", date).is_err());
+
+        // without the date in the response, on the requested date
+        let mut config = vanguard().config;
+        config.date = None;
+        let actual = JsonDownloader::new("nav", &config).map_price(&body, "EUR", date).unwrap();
+        assert_eq!("2024-01-31 00:00:00", actual.datetime.to_string());
+    }
+
+    #[test]
+    fn test_parse_dates() {
+        let (datetime, offset) = parse_date(&Value::from(1706718543), "timestamp").unwrap();
+        assert_eq!("2024-01-31 16:29:03", datetime.to_string());
+        assert_eq!(Some(Utc.fix()), offset);
+
+        let (datetime, offset) =
+            parse_date(&Value::from("2024-01-31T17:35:00+0100"), "%Y-%m-%dT%H:%M:%S%z").unwrap();
+        assert_eq!("2024-01-31 17:35:00", datetime.to_string());
+        assert_eq!(FixedOffset::east_opt(3600), offset);
+
+        let (datetime, _) = parse_date(&Value::from("31.01.2024"), "%d.%m.%Y").unwrap();
+        assert_eq!("2024-01-31 00:00:00", datetime.to_string());
+    }
+}
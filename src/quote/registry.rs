@@ -0,0 +1,233 @@
+/*!
+ * The registry of the price providers (downloaders), by agent name.
+ *
+ * The agent in the symbols file (`updater`) selects the downloader. The built-in
+ * agents are registered in `DownloaderRegistry::default()`. Applications using pricedb
+ * as a library can register their own, or replace the built-in ones:
+ *
+ * let mut app = App::new(config);
+ * app.registry_mut().register("my_agent", |_config, _cache_mode| Arc::new(MyDownloader::new()));
+ *
+ * The JSON providers from the configuration are registered by their names, and can
+ * not replace the other agents.
+ */
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    cache::CacheMode,
+    config::{JsonProviderConfig, PriceDbConfig},
+};
+
+use super::{
+    coingecko::CoinGeckoDownloader, exec::ExecDownloader, fixerio::Fixerio,
+    html_page::HtmlPageDownloader, json_provider::JsonDownloader, stooq::StooqDownloader,
+    vanguard_au_2023_detail::VanguardAu3Downloader,
+    yahoo_finance_downloader::YahooFinanceDownloader, yahoo_v2::YahooQuoteSummaryDownloader,
+    Downloader,
+};
+
+/// Creates the downloader, using the configuration (i.e. the symbol mappings) and the
+/// cache mode of the run.
+pub type DownloaderFactory =
+    Arc<dyn Fn(&PriceDbConfig, CacheMode) -> Arc<dyn Downloader> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct DownloaderRegistry {
+    factories: HashMap<String, DownloaderFactory>,
+}
+
+impl DownloaderRegistry {
+    /// An empty registry, without the built-in agents.
+    pub fn new() -> Self {
+        Self {
+            factories: HashMap::new(),
+        }
+    }
+
+    /// Registers the downloader factory under the agent name, replacing any existing one.
+    pub fn register<F>(&mut self, agent: &str, factory: F)
+    where
+        F: Fn(&PriceDbConfig, CacheMode) -> Arc<dyn Downloader> + Send + Sync + 'static,
+    {
+        self.factories.insert(agent.to_owned(), Arc::new(factory));
+    }
+
+    /// Registers a downloader instance, shared by all the downloads.
+    pub fn register_downloader(&mut self, agent: &str, downloader: Arc<dyn Downloader>) {
+        self.register(agent, move |_, _| downloader.clone());
+    }
+
+    /// Registers the JSON providers (`json_providers` configuration) under their names.
+    /// A provider with the name of a registered agent is an error.
+    pub fn register_json_providers(
+        &mut self,
+        providers: &HashMap<String, JsonProviderConfig>,
+    ) -> Result<()> {
+        for (name, provider) in providers {
+            if self.contains(name) {
+                return Err(anyhow!(
+                    "The JSON provider {name} has the name of an existing agent. Rename it."
+                ));
+            }
+
+            let agent = name.to_owned();
+            let provider = provider.clone();
+            self.register(name, move |_, _| Arc::new(JsonDownloader::new(&agent, &provider)));
+        }
+
+        Ok(())
+    }
+
+    pub fn contains(&self, agent: &str) -> bool {
+        self.factories.contains_key(agent)
+    }
+
+    /// The registered agent names, sorted.
+    pub fn agents(&self) -> Vec<&str> {
+        let mut agents: Vec<&str> = self.factories.keys().map(String::as_str).collect();
+        agents.sort();
+        agents
+    }
+
+    /// Creates the downloader for the agent.
+    pub fn create(
+        &self,
+        agent: &str,
+        config: &PriceDbConfig,
+        cache_mode: CacheMode,
+    ) -> Option<Arc<dyn Downloader>> {
+        self.factories
+            .get(agent)
+            .map(|factory| factory(config, cache_mode))
+    }
+}
+
+impl Default for DownloaderRegistry {
+    /// The registry with the built-in agents.
+    fn default() -> Self {
+        let mut registry = Self::new();
+
+        registry.register("yahoo_finance", |config, _| {
+            let mut downloader = YahooFinanceDownloader::new();
+            downloader.set_namespaces(&config.yahoo_namespaces);
+            Arc::new(downloader)
+        });
+        registry.register("yahoo_quote_summary", |config, _| {
+            let mut downloader = YahooQuoteSummaryDownloader::new();
+            downloader.set_namespaces(&config.yahoo_namespaces);
+            Arc::new(downloader)
+        });
+        registry.register("fixerio", |config, cache_mode| {
+            let mut downloader = Fixerio::new(config);
+            downloader.set_cache_mode(cache_mode);
+            Arc::new(downloader)
+        });
+        registry.register("coingecko", |config, _| {
+            let mut downloader = CoinGeckoDownloader::new();
+            downloader.set_api_key(&config.coingecko_api_key);
+            downloader.add_coins(&config.coingecko_coins);
+            Arc::new(downloader)
+        });
+        registry.register("exec", |config, _| Arc::new(ExecDownloader::new(&config.exec)));
+        registry.register("stooq", |config, _| {
+            let mut downloader = StooqDownloader::new();
+            downloader.add_namespaces(&config.stooq_namespaces);
+            Arc::new(downloader)
+        });
+        registry.register("html", |config, _| {
+            let mut downloader = HtmlPageDownloader::new();
+            downloader.add_pages(&config.html_pages);
+            Arc::new(downloader)
+        });
+        registry.register("vanguard_au", |config, _| {
+            let mut downloader = VanguardAu3Downloader::new();
+            downloader.add_funds(&config.vanguard_au_funds);
+            Arc::new(downloader)
+        });
+
+        registry
+    }
+}
+
+impl fmt::Debug for DownloaderRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.agents()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use rust_decimal_macros::dec;
+
+    use crate::model::{Price, SecuritySymbol};
+
+    use super::*;
+
+    struct FixedPrice;
+
+    #[async_trait]
+    impl Downloader for FixedPrice {
+        async fn download(&self, _security_symbol: &SecuritySymbol, currency: &str) -> Result<Price> {
+            Ok(Price {
+                value: dec!(42),
+                currency: currency.to_owned(),
+                ..Price::default()
+            })
+        }
+    }
+
+    #[test]
+    fn test_builtin_agents() {
+        let registry = DownloaderRegistry::default();
+
+        assert_eq!(
+            vec!["coingecko", "exec", "fixerio", "html", "stooq", "vanguard_au", "yahoo_finance", "yahoo_quote_summary"],
+            registry.agents()
+        );
+        assert!(DownloaderRegistry::new().agents().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_custom_agent() {
+        let mut registry = DownloaderRegistry::default();
+        registry.register_downloader("fixed", Arc::new(FixedPrice));
+        // replaces the built-in one
+        registry.register("stooq", |_, _| Arc::new(FixedPrice));
+
+        let config = PriceDbConfig::default();
+        let symbol = SecuritySymbol::new("XETRA:EL4X");
+
+        for agent in ["fixed", "stooq"] {
+            let downloader = registry.create(agent, &config, CacheMode::Off).unwrap();
+            let price = downloader.download(&symbol, "EUR").await.unwrap();
+            assert_eq!(dec!(42), price.value);
+        }
+        assert!(registry.create("unknown", &config, CacheMode::Off).is_none());
+    }
+
+    #[test]
+    fn test_json_providers() {
+        let provider = JsonProviderConfig {
+            url: "https://example.com/{symbol}".into(),
+            headers: HashMap::new(),
+            value: "/price".into(),
+            date: None,
+            date_format: "%Y-%m-%d".into(),
+            currency: None,
+        };
+        let mut registry = DownloaderRegistry::default();
+
+        registry
+            .register_json_providers(&HashMap::from([("my_api".to_string(), provider.clone())]))
+            .unwrap();
+        assert!(registry.contains("my_api"));
+
+        // can not shadow a built-in agent
+        let clash = HashMap::from([("stooq".to_string(), provider)]);
+        assert!(registry.register_json_providers(&clash).is_err());
+    }
+}
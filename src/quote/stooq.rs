@@ -0,0 +1,269 @@
+/*!
+ * Stooq daily prices, from the CSV endpoints.
+ *
+ * The latest price:
+ * https://stooq.com/q/l/?s=el4x.de&f=sd2t2ohlcv&h&e=csv
+ * The historical daily prices:
+ * https://stooq.com/q/d/l/?s=el4x.de&d1=20240131&d2=20240131&i=d
+ *
+ * The namespace (exchange) is mapped to the Stooq suffix, i.e. XETRA:EL4X -> el4x.de.
+ * Symbols without a namespace, i.e. indices like ^SPX, are used as they are.
+ * Stooq does not provide the currency, so the requested one is used. The UK
+ * listings are quoted in pence, so their GBP prices are labelled GBp, as by Yahoo.
+ */
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone};
+use chrono_tz::Europe::Warsaw;
+use rust_decimal::Decimal;
+
+use crate::model::{Price, SecuritySymbol};
+
+use super::Downloader;
+
+pub struct StooqDownloader {
+    url: String,
+    namespaces: HashMap<String, String>,
+}
+
+impl StooqDownloader {
+    pub fn new() -> Self {
+        let namespaces = [
+            ("BATS", "us"),
+            ("FWB", "de"),
+            ("HKEX", "hk"),
+            ("LSE", "uk"),
+            ("NASDAQ", "us"),
+            ("NYSE", "us"),
+            ("NYSEARCA", "us"),
+            ("TSE", "jp"),
+            ("XETRA", "de"),
+        ]
+        .into_iter()
+        .map(|(namespace, suffix)| (namespace.to_owned(), suffix.to_owned()))
+        .collect();
+
+        Self {
+            url: "https://stooq.com/q".to_string(),
+            namespaces,
+        }
+    }
+
+    /// Adds the given namespace -> suffix mapping over the built-in one.
+    pub fn add_namespaces(&mut self, custom_namespaces: &HashMap<String, String>) {
+        for (namespace, suffix) in custom_namespaces {
+            self.namespaces
+                .insert(namespace.to_uppercase(), suffix.to_lowercase());
+        }
+    }
+
+    fn get_stooq_symbol(&self, symbol: &SecuritySymbol) -> Result<String> {
+        let mnemonic = symbol.mnemonic.to_lowercase();
+        if symbol.namespace.is_empty() {
+            return Ok(mnemonic);
+        }
+
+        let suffix = self.namespaces.get(&symbol.namespace).ok_or_else(|| {
+            anyhow!(
+                "Unknown namespace {} for Stooq ({}). Add it to stooq_namespaces in the configuration.",
+                symbol.namespace,
+                symbol
+            )
+        })?;
+
+        Ok(format!("{mnemonic}.{suffix}"))
+    }
+
+    fn latest_url(&self, stooq_symbol: &str) -> String {
+        format!("{}/l/?s={stooq_symbol}&f=sd2t2ohlcv&h&e=csv", self.url)
+    }
+
+    fn history_url(&self, stooq_symbol: &str, date: NaiveDate) -> String {
+        let date = date.format("%Y%m%d");
+        format!("{}/d/l/?s={stooq_symbol}&d1={date}&d2={date}&i=d", self.url)
+    }
+
+    async fn get_csv(&self, url: &str) -> Result<String> {
+        log::debug!("fetching from {:?}", url);
+
+        let response = reqwest::get(url).await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Stooq error: {}", response.status()));
+        }
+
+        Ok(response.text().await?)
+    }
+}
+
+#[async_trait]
+impl Downloader for StooqDownloader {
+    async fn download(&self, security_symbol: &SecuritySymbol, currency: &str) -> Result<Price> {
+        let stooq_symbol = self.get_stooq_symbol(security_symbol)?;
+        let content = self.get_csv(&self.latest_url(&stooq_symbol)).await?;
+
+        parse_csv(&content, &get_currency(&stooq_symbol, currency))
+            .with_context(|| format!("No Stooq price for {stooq_symbol}"))
+    }
+
+    fn supports_historical(&self) -> bool {
+        true
+    }
+
+    async fn download_historical(
+        &self,
+        security_symbol: &SecuritySymbol,
+        currency: &str,
+        date: NaiveDate,
+    ) -> Result<Price> {
+        let stooq_symbol = self.get_stooq_symbol(security_symbol)?;
+        let content = self.get_csv(&self.history_url(&stooq_symbol, date)).await?;
+
+        parse_csv(&content, &get_currency(&stooq_symbol, currency))
+            .with_context(|| format!("No Stooq price for {stooq_symbol} on {date}"))
+    }
+}
+
+/// The currency of the price. The UK (.uk) prices are in pence.
+fn get_currency(stooq_symbol: &str, currency: &str) -> String {
+    let currency = currency.to_uppercase();
+    if stooq_symbol.ends_with(".uk") && currency == "GBP" {
+        return "GBp".to_owned();
+    }
+
+    currency
+}
+
+/// Reads the close price from the (last row of the) CSV, i.e.
+/// Symbol,Date,Time,Open,High,Low,Close,Volume
+/// EL4X.DE,2024-01-31,17:35:14,13.2,13.28,13.18,13.244,11503
+/// The historical data has no Symbol and Time columns. Missing values are "N/D".
+fn parse_csv(content: &str, currency: &str) -> Result<Price> {
+    let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| anyhow!("Empty response"))?
+        .split(',')
+        .map(str::trim)
+        .collect();
+    let row: Vec<&str> = lines
+        .next_back()
+        .ok_or_else(|| anyhow!("No data: {}", header.join(",")))?
+        .split(',')
+        .map(str::trim)
+        .collect();
+
+    let column = |name: &str| -> Result<&str> {
+        let index = header
+            .iter()
+            .position(|title| title.eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!("No {name} column in the Stooq response"))?;
+        match row.get(index) {
+            Some(&"N/D") | None => Err(anyhow!("No {name} value in the Stooq response")),
+            Some(value) => Ok(value),
+        }
+    };
+
+    let date = NaiveDate::parse_from_str(column("Date")?, "%Y-%m-%d")?;
+    let value = Decimal::from_str_exact(column("Close")?)?;
+
+    let (datetime, offset) = match column("Time") {
+        // Stooq times are in Warsaw (CET/CEST).
+        Ok(time) => {
+            let datetime = NaiveDateTime::new(date, NaiveTime::parse_from_str(time, "%H:%M:%S")?);
+            let offset = Warsaw
+                .offset_from_local_datetime(&datetime)
+                .single()
+                .map(|offset| offset.fix());
+            (datetime, offset)
+        }
+        Err(_) => (date.and_time(Default::default()), None),
+    };
+
+    Ok(Price {
+        datetime,
+        offset,
+        value,
+        currency: currency.to_owned(),
+        ..Price::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::FixedOffset;
+
+    use super::*;
+
+    #[test]
+    fn test_symbols() {
+        let mut dl = StooqDownloader::new();
+        dl.add_namespaces(&HashMap::from([("six".to_string(), "CH".to_string())]));
+
+        assert_eq!("el4x.de", dl.get_stooq_symbol(&SecuritySymbol::new("XETRA:EL4X")).unwrap());
+        assert_eq!("nesn.ch", dl.get_stooq_symbol(&SecuritySymbol::new("SIX:NESN")).unwrap());
+        assert_eq!("^spx", dl.get_stooq_symbol(&SecuritySymbol::new("^SPX")).unwrap());
+        assert!(dl.get_stooq_symbol(&SecuritySymbol::new("ASX:VHY")).is_err());
+    }
+
+    #[test]
+    fn test_currency_in_pence() {
+        let dl = StooqDownloader::new();
+        let stooq_symbol = dl.get_stooq_symbol(&SecuritySymbol::new("LSE:VOD")).unwrap();
+        let content = "Symbol,Date,Time,Open,High,Low,Close,Volume\nVOD.UK,2024-01-31,17:35:11,69.1,70,68.9,69.5,1000\n";
+
+        let actual = parse_csv(content, &get_currency(&stooq_symbol, "gbp")).unwrap();
+
+        assert_eq!("69.5", actual.value.to_string());
+        assert_eq!("GBp", actual.currency);
+        assert_eq!("EUR", get_currency("el4x.de", "eur"));
+    }
+
+    #[test]
+    fn test_urls() {
+        let dl = StooqDownloader::new();
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+
+        assert_eq!(
+            "https://stooq.com/q/l/?s=el4x.de&f=sd2t2ohlcv&h&e=csv",
+            dl.latest_url("el4x.de")
+        );
+        assert_eq!(
+            "https://stooq.com/q/d/l/?s=el4x.de&d1=20240131&d2=20240131&i=d",
+            dl.history_url("el4x.de", date)
+        );
+    }
+
+    #[test]
+    fn test_parse_latest() {
+        let content = "Symbol,Date,Time,Open,High,Low,Close,Volume\r\n\
+            EL4X.DE,2024-01-31,17:35:14,13.2,13.28,13.18,13.244,11503\r\n";
+
+        let actual = parse_csv(content, "EUR").unwrap();
+
+        assert_eq!("13.244", actual.value.to_string());
+        assert_eq!("EUR", actual.currency);
+        assert_eq!("2024-01-31 17:35:14", actual.datetime.to_string());
+        assert_eq!(FixedOffset::east_opt(3600), actual.offset);
+    }
+
+    #[test]
+    fn test_parse_historical() {
+        let content = "Date,Open,High,Low,Close,Volume\n2024-07-31,13.5,13.6,13.4,13.550,9876\n";
+
+        let actual = parse_csv(content, "EUR").unwrap();
+
+        assert_eq!("13.550", actual.value.to_string());
+        assert_eq!("2024-07-31 00:00:00", actual.datetime.to_string());
+        assert_eq!(None, actual.offset);
+    }
+
+    #[test]
+    fn test_parse_no_data() {
+        let latest = "Symbol,Date,Time,Open,High,Low,Close,Volume\nXYZ.DE,N/D,N/D,N/D,N/D,N/D,N/D,N/D\n";
+
+        assert!(parse_csv(latest, "EUR").is_err());
+        assert!(parse_csv("No data", "EUR").is_err());
+    }
+}
@@ -7,7 +7,7 @@ use std::{collections::HashMap, str::FromStr};
 use anyhow::Result;
 use async_trait::async_trait;
 use chrono::NaiveDate;
-use rust_decimal::{prelude::ToPrimitive, Decimal};
+use rust_decimal::Decimal;
 use serde_json::Value;
 
 use crate::model::{Price, SecuritySymbol};
@@ -80,7 +80,7 @@ impl VanguardAuDownloader {
         let mut price = Price::new();
 
         let x = NaiveDate::parse_from_str(fund_info.date.as_str(), "%d %b %Y").unwrap();
-        price.date = x.to_string();
+        price.datetime = x.and_time(Default::default());
 
         // price.symbol = SecuritySymbol {
         //     namespace: "VANGUARD".to_string(),
@@ -88,9 +88,7 @@ impl VanguardAuDownloader {
         // };
 
         let value_str = fund_info.value.strip_prefix('$').unwrap();
-        let value = Decimal::from_str(value_str)?;
-        price.value = value.mantissa().to_i64().unwrap();
-        price.denom = 10_i64.pow(value.scale()); // in 10^3 = 1000, scale=3, denom=1000
+        price.value = Decimal::from_str(value_str)?;
 
         price.currency = "AUD".to_string();
 
@@ -161,6 +159,6 @@ mod tests {
     //     let actual = dl.download(&symbol, "AUD").await.expect("downloaded price");
 
     //     assert!(!actual.currency.is_empty());
-    //     assert!(actual.value != 0);
+    //     assert!(!actual.value.is_zero());
     // }
 }
\ No newline at end of file
@@ -10,32 +10,55 @@
 /// https://www.vanguard.com.au/personal/api/products/personal/fund/8105/prices?limit=-1
 use super::Downloader;
 use crate::model::{Price, SecuritySymbol};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use chrono::NaiveDate;
-use rust_decimal::{prelude::ToPrimitive, Decimal};
+use rust_decimal::Decimal;
 use serde_json::Value;
 use std::{collections::HashMap, str::FromStr};
 
 pub(crate) struct VanguardAu3Downloader {
-    funds_map: HashMap<&'static str, &'static str>,
+    funds_map: HashMap<String, String>,
 }
 
 impl VanguardAu3Downloader {
     pub fn new() -> Self {
-        let funds_map = HashMap::from([
+        let funds_map = [
             // ("VANGUARD:BOND", "8123"),
             // ("VANGUARD:HINT", "8146"),
             ("VANGUARD:PROP", "8105"), // VAN0004AU
             ("VANGUARD:HY", "8106"), // VAN0104AU
-        ]);
+        ]
+        .into_iter()
+        .map(|(symbol, port_id)| (symbol.to_owned(), port_id.to_owned()))
+        .collect();
 
         Self { funds_map }
     }
 
-    fn get_url(&self, symbol: &SecuritySymbol) -> String {
+    /// Adds the given symbol -> port ID mapping over the built-in one.
+    pub fn add_funds(&mut self, custom_funds: &HashMap<String, String>) {
+        for (symbol, port_id) in custom_funds {
+            self.funds_map.insert(symbol.to_uppercase(), port_id.to_owned());
+        }
+    }
+
+    /// The fund's port ID. A numeric symbol (i.e. the `updater_symbol`) is the port ID.
+    fn get_fund_id(&self, symbol: &SecuritySymbol) -> Result<String> {
+        if !symbol.mnemonic.is_empty() && symbol.mnemonic.chars().all(|c| c.is_ascii_digit()) {
+            return Ok(symbol.mnemonic.to_owned());
+        }
+
         let sec_symbol = symbol.to_string();
-        let fund_id = self.funds_map.get(sec_symbol.as_str()).unwrap();
+        self.funds_map.get(sec_symbol.as_str()).cloned().ok_or_else(|| {
+            anyhow!(
+                "No Vanguard fund (port) ID for {sec_symbol}. Set it in vanguard_au_funds in the configuration, or as the updater_symbol."
+            )
+        })
+    }
+
+    fn get_url(&self, symbol: &SecuritySymbol) -> Result<String> {
+        let fund_id = self.get_fund_id(symbol)?;
         let result = format!(
             "https://www.vanguard.com.au/personal/api/products/personal/fund/{}/detail?limit=-1",
             fund_id
@@ -43,13 +66,13 @@ impl VanguardAu3Downloader {
 
         // log::debug!("url: {:?}", result);
 
-        result
+        Ok(result)
     }
 
     /// Returns the latest retail fund price.
     /// (date, price, currency)
     async fn dl_price(&self, symbol: &SecuritySymbol) -> Result<(String, String, String)> {
-        let url = self.get_url(symbol);
+        let url = self.get_url(symbol)?;
 
         let response = reqwest::get(url).await?;
         let content = response.text().await?;
@@ -71,12 +94,12 @@ impl VanguardAu3Downloader {
     fn parse_price(&self, date: String, price: String, currency: String) -> Result<Price> {
         let mut p = Price::new();
 
+        // The NAV is only dated. Without the offset, the date is kept in any
+        // timestamp mode.
         let x = NaiveDate::parse_from_str(&date, "%Y-%m-%d")?;
-        p.date = x.to_string();
+        p.datetime = x.and_time(Default::default());
 
-        let value = Decimal::from_str(&price)?;
-        p.value = value.mantissa().to_i64().unwrap();
-        p.denom = 10_i64.pow(value.scale()); // in 10^3 = 1000, scale=3, denom=1000
+        p.value = Decimal::from_str(&price)?;
 
         p.currency = currency;
 
@@ -104,7 +127,9 @@ impl Downloader for VanguardAu3Downloader {
 
 #[cfg(test)]
 mod tests {
-    use crate::{model::SecuritySymbol, quote::Downloader};
+    use std::collections::HashMap;
+
+    use crate::{config::TimestampMode, model::SecuritySymbol, quote::Downloader};
 
     use super::VanguardAu3Downloader;
 
@@ -113,7 +138,7 @@ mod tests {
         let symbol = SecuritySymbol::new("VANGUARD:HY");
         let dl = VanguardAu3Downloader::new();
 
-        let actual = dl.get_url(&symbol);
+        let actual = dl.get_url(&symbol).unwrap();
 
         assert_eq!(
             "https://www.vanguard.com.au/personal/api/products/personal/fund/8106/detail?limit=-1",
@@ -122,6 +147,34 @@ mod tests {
         // "https://www.vanguard.com.au/personal/api/products/personal/fund/8106/prices?limit=-1",
     }
 
+    #[test]
+    fn test_fund_id_sources() {
+        let custom = HashMap::from([("vanguard:bond".to_string(), "8123".to_string())]);
+        let mut dl = VanguardAu3Downloader::new();
+        dl.add_funds(&custom);
+
+        let configured = dl.get_fund_id(&SecuritySymbol::new("VANGUARD:BOND")).unwrap();
+        let numeric = dl.get_fund_id(&SecuritySymbol::new("VANGUARD:8146")).unwrap();
+        let missing = dl.get_fund_id(&SecuritySymbol::new("VANGUARD:HINT"));
+
+        assert_eq!("8123", configured);
+        assert_eq!("8146", numeric);
+        assert!(missing.unwrap_err().to_string().contains("VANGUARD:HINT"));
+    }
+
+    #[test]
+    fn test_parse_price_date() {
+        let dl = VanguardAu3Downloader::new();
+
+        let actual = dl.parse_price("2024-01-31".into(), "1.2345".into(), "AUD".into()).unwrap();
+
+        assert_eq!("2024-01-31 00:00:00", actual.datetime.to_string());
+        assert_eq!(None, actual.offset);
+        // the same date in UTC
+        let utc = TimestampMode::Utc.apply(actual.datetime, actual.offset);
+        assert_eq!("2024-01-31 00:00:00", utc.to_string());
+    }
+
     /// Dev debug test. Uncomment to execute.
     // #[tokio::test]
     async fn test_hy_price_dl() {
@@ -134,8 +187,6 @@ mod tests {
         assert!(!actual.currency.is_empty());
         assert_eq!("AUD", actual.currency);
         // value
-        assert!(actual.value != 0);
-        // date
-        assert!(!actual.date.is_empty());
+        assert!(!actual.value.is_zero());
     }
 }
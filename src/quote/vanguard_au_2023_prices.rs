@@ -7,7 +7,7 @@ use crate::model::{Price, SecuritySymbol};
 use anyhow::Result;
 use async_trait::async_trait;
 use chrono::NaiveDate;
-use rust_decimal::{prelude::ToPrimitive, Decimal};
+use rust_decimal::Decimal;
 use serde_json::Value;
 use std::{collections::HashMap, str::FromStr};
 
@@ -65,11 +65,9 @@ impl VanguardAu2Downloader {
         let mut p = Price::new();
 
         let x = NaiveDate::parse_from_str(&date, "%Y-%m-%d")?;
-        p.date = x.to_string();
+        p.datetime = x.and_time(Default::default());
 
-        let value = Decimal::from_str(&price)?;
-        p.value = value.mantissa().to_i64().unwrap();
-        p.denom = 10_i64.pow(value.scale()); // in 10^3 = 1000, scale=3, denom=1000
+        p.value = Decimal::from_str(&price)?;
 
         p.currency = currency;
 
@@ -126,8 +124,6 @@ mod tests {
         assert!(!actual.currency.is_empty());
         assert_eq!("AUD", actual.currency);
         // value
-        assert!(actual.value != 0);
-        // date
-        assert!(!actual.date.is_empty());
+        assert!(!actual.value.is_zero());
     }
 }
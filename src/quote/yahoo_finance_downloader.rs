@@ -1,114 +1,122 @@
 use std::collections::HashMap;
 
 use async_trait::async_trait;
-use chrono::{NaiveDateTime, TimeZone, FixedOffset};
+use chrono::{DateTime, FixedOffset, NaiveDate};
 use reqwest::header::{HeaderMap, USER_AGENT};
-use rust_decimal::{
-    prelude::{FromPrimitive, ToPrimitive},
-    Decimal,
-};
+use rust_decimal::prelude::ToPrimitive;
 use serde_json::Value;
 
-use crate::model::{Price, SecuritySymbol};
+use crate::model::{CorporateAction, CorporateActionKind, Price, SecuritySymbol};
 
-use anyhow::{Ok, Result};
+use anyhow::{anyhow, Ok, Result};
 
-use super::Downloader;
+use super::{parse_decimal, Downloader};
 
 /// YahooFinanceDownloader
 #[derive(Debug)]
 pub struct YahooFinanceDownloader {
     url: String,
-    namespaces: HashMap<&'static str, &'static str>,
+    namespaces: HashMap<String, String>,
 }
 
 impl YahooFinanceDownloader {
     pub fn new() -> Self {
-        let namespaces = HashMap::from([
-            ("AMS", "AS"),
-            ("ASX", "AX"),
-            ("BATS", ""),
-            ("BVME", "MI"),
-            ("FWB", "F"),
-            ("LSE", "L"),
-            ("NASDAQ", ""),
-            ("NYSE", ""),
-            ("NYSEARCA", ""),
-            ("XETRA", "DE"),
-        ]);
-
         Self {
             url: "https://query1.finance.yahoo.com/v8/finance/chart/".to_string(),
-            namespaces,
+            namespaces: get_namespaces(&HashMap::new()),
         }
     }
 
-    fn assemble_url(&self, symbol: &SecuritySymbol) -> String {
-        let current_namespace = symbol.namespace.as_str();
-        let mut local_namespace: &str = current_namespace;
+    /// Applies the given namespace -> suffix mapping over the built-in one.
+    pub fn set_namespaces(&mut self, custom_namespaces: &HashMap<String, String>) {
+        self.namespaces = get_namespaces(custom_namespaces);
+    }
+
+    fn assemble_url(&self, symbol: &SecuritySymbol) -> Result<String> {
+        let local_symbol = get_local_symbol(&self.namespaces, symbol)?;
 
-        if self.namespaces.contains_key(current_namespace) {
-            local_namespace = self.namespaces[current_namespace];
-        }
+        Ok(format!("{}{}", self.url, local_symbol))
+    }
+
+    /// The daily chart with the dividend and split events between the dates.
+    fn assemble_events_url(&self, symbol: &SecuritySymbol, from: NaiveDate, to: NaiveDate) -> Result<String> {
+        let period1 = from.and_time(Default::default()).and_utc().timestamp();
+        let period2 = to.succ_opt().unwrap_or(to).and_time(Default::default()).and_utc().timestamp();
+
+        Ok(format!(
+            "{}?period1={period1}&period2={period2}&interval=1d&events=div,splits",
+            self.assemble_url(symbol)?
+        ))
+    }
+
+    async fn get_json(&self, url: &str) -> Result<Value> {
+        log::debug!("fetching from {:?}", url);
+
+        let user_agent = "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:124.0) Gecko/20100101 Firefox/124.0";
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, user_agent.parse().unwrap());
 
-        let mut url = format!("{}{}", self.url, symbol.mnemonic);
+        let client = reqwest::Client::new();
+        let response = client.get(url)
+            .headers(headers)
+            .send()
+            .await?;
 
-        if !local_namespace.is_empty() {
-            url = format!("{}.{}", url, local_namespace);
+        if !response.status().is_success() {
+            return Err(anyhow!("Received a non-success status: {}", response.status()));
         }
 
-        url
+        let body = response
+            .json::<Value>()
+            .await?;
+
+        Ok(body)
     }
 
     /// Extract the Price from JSON.
     ///
     fn get_price_from_json(&self, body: &Value) -> Result<Price> {
         let chart = &body["chart"];
-        let error = &chart["error"];
-
-        // todo: ensure that there is no error!
-        //log::debug!("error? {:?}", error);
-        assert_eq!(*error, Value::Null);
+        if !chart["error"].is_null() {
+            return Err(anyhow!("Yahoo error: {}", chart["error"]["description"]));
+        }
 
         let mut result = Price::new();
 
-        let meta = &body["chart"]["result"][0]["meta"];
-        assert_ne!(*meta, Value::Null);
+        let meta = &chart["result"][0]["meta"];
+        if meta.is_null() {
+            return Err(anyhow!("No price in the Yahoo response"));
+        }
 
         // Price
 
-        let market_price = meta["regularMarketPrice"].as_f64().unwrap();
-        // log::debug!("market price {:?}", market_price);
-        // Parse using Decimal.
-        let d = Decimal::from_f64(market_price).unwrap();
-        // log::debug!("Decimal -> {:?} {:?}", d.mantissa(), d.scale());
-        result.value = d.mantissa().to_i64().unwrap();
-        result.denom = 10_i64.pow(d.scale()); // in 10^3 = 1000, scale=3, denom=1000
+        result.value = parse_decimal(&meta["regularMarketPrice"])?;
 
         // Currency
 
-        result.currency = meta["currency"].as_str().unwrap().to_string();
+        result.currency = meta["currency"]
+            .as_str()
+            .ok_or_else(|| anyhow!("No currency in the Yahoo response"))?
+            .to_string();
 
         // Date
 
-        let seconds = meta["regularMarketTime"].as_i64().unwrap();
-        // log::debug!("seconds {:?}", seconds);
-        let offset = meta["gmtoffset"].as_i64().unwrap().to_i32().unwrap();
-        let fo = FixedOffset::east_opt(offset).unwrap();
-
-        let utc = NaiveDateTime::from_timestamp_opt(seconds, 0).unwrap();
-        // log::debug!("time {:?}", date_time);
-        let dt_fo = fo.from_utc_datetime(&utc);
+        let seconds = meta["regularMarketTime"]
+            .as_i64()
+            .ok_or_else(|| anyhow!("No price time in the Yahoo response"))?;
+        let offset = meta["gmtoffset"]
+            .as_i64()
+            .and_then(|offset| offset.to_i32())
+            .and_then(FixedOffset::east_opt)
+            .ok_or_else(|| anyhow!("Invalid gmtoffset {}", meta["gmtoffset"]))?;
 
-        let date_str = dt_fo.date_naive().to_string();
-        // log::debug!("Parsed date is {:?}", date_str);
-        result.date = date_str;
+        let dt_fo = DateTime::from_timestamp(seconds, 0)
+            .ok_or_else(|| anyhow!("Invalid price time {seconds}"))?
+            .with_timezone(&offset);
 
-        // Time
-
-        let time_str = dt_fo.time().to_string();
-        // log::debug!("Parsed time is {:?}", time_str);
-        result.time = time_str;
+        // Date and time, at the exchange.
+        result.datetime = dt_fo.naive_local();
+        result.offset = Some(offset);
 
         Ok(result)
     }
@@ -117,43 +125,150 @@ impl YahooFinanceDownloader {
 #[async_trait]
 impl Downloader for YahooFinanceDownloader {
     async fn download(&self, security_symbol: &SecuritySymbol, _currency: &str) -> Result<Price> {
-        let url = self.assemble_url(&security_symbol);
+        let url = self.assemble_url(security_symbol)?;
+        let body = self.get_json(&url).await?;
 
-        log::debug!("fetching from {:?}", url);
+        // log::debug!("something downloaded: {:?}", body);
 
-        let user_agent = "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:124.0) Gecko/20100101 Firefox/124.0";
-        let mut headers = HeaderMap::new();
-        headers.insert(USER_AGENT, user_agent.parse().unwrap());
+        let result = self.get_price_from_json(&body)?;
 
-        let client = reqwest::Client::new();
-        // let response = reqwest::get(url)
-        let response = client.get(url)
-            .headers(headers)
-            .send()
-            .await?;
+        Ok(result)
+    }
+
+    async fn download_actions(
+        &self,
+        security_symbol: &SecuritySymbol,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<CorporateAction>> {
+        let url = self.assemble_events_url(security_symbol, from, to)?;
+        let body = self.get_json(&url).await?;
+
+        let actions = get_actions_from_json(&body, &security_symbol.to_string())?;
+
+        Ok(actions
+            .into_iter()
+            .filter(|action| action.date >= from && action.date <= to)
+            .collect())
+    }
+}
 
-            if !response.status().is_success() {
-            println!("Received a non-success status: {}", response.status());
-            //println!("Response message: {}", response.text());
+/// Reads the dividend and split events from the chart response, i.e.
+/// "events": {"dividends": {"1710457200": {"amount": 0.85, "date": 1710457200}},
+///   "splits": {"1718001000": {"date": 1718001000, "numerator": 10, "denominator": 1}}}
+/// The dates are at the exchange. The dividends are in the currency of the prices.
+fn get_actions_from_json(body: &Value, symbol: &str) -> Result<Vec<CorporateAction>> {
+    let chart = &body["chart"];
+    if !chart["error"].is_null() {
+        return Err(anyhow!("Yahoo error: {}", chart["error"]["description"]));
+    }
+
+    let result = &chart["result"][0];
+    let meta = &result["meta"];
+    // As reported, like the prices. The LSE dividends are in pence (GBp).
+    let currency = meta["currency"].as_str().unwrap_or_default();
+    let offset = FixedOffset::east_opt(meta["gmtoffset"].as_i64().unwrap_or_default().to_i32().unwrap_or_default())
+        .ok_or_else(|| anyhow!("Invalid gmtoffset"))?;
+
+    let get_date = |event: &Value| -> Result<NaiveDate> {
+        let seconds = event["date"].as_i64().ok_or_else(|| anyhow!("No event date"))?;
+        let datetime = DateTime::from_timestamp(seconds, 0)
+            .ok_or_else(|| anyhow!("Invalid event date {seconds}"))?;
+        Ok(datetime.with_timezone(&offset).date_naive())
+    };
+
+    let mut actions = vec![];
+    let events = &result["events"];
+
+    if let Some(dividends) = events["dividends"].as_object() {
+        for event in dividends.values() {
+            actions.push(CorporateAction {
+                symbol: symbol.to_owned(),
+                date: get_date(event)?,
+                kind: CorporateActionKind::Dividend {
+                    amount: parse_decimal(&event["amount"])?,
+                    currency: currency.to_owned(),
+                },
+            });
         }
+    }
 
-        let body = response
-            //.text()
-            .json::<Value>()
-            .await?;
+    if let Some(splits) = events["splits"].as_object() {
+        for event in splits.values() {
+            actions.push(CorporateAction {
+                symbol: symbol.to_owned(),
+                date: get_date(event)?,
+                kind: CorporateActionKind::Split {
+                    numerator: parse_decimal(&event["numerator"])?,
+                    denominator: parse_decimal(&event["denominator"])?,
+                },
+            });
+        }
+    }
 
-        // log::debug!("something downloaded: {:?}", body);
+    actions.sort_by_key(|action| action.date);
 
-        let result = self.get_price_from_json(&body)?;
+    Ok(actions)
+}
 
-        Ok(result)
+/// The built-in namespace (exchange) -> Yahoo suffix mapping, with the custom
+/// mapping from the configuration applied over it.
+pub(crate) fn get_namespaces(custom_namespaces: &HashMap<String, String>) -> HashMap<String, String> {
+    let mut namespaces: HashMap<String, String> = [
+        ("AMS", "AS"),
+        ("ASX", "AX"),
+        ("BATS", ""),
+        ("BVME", "MI"),
+        ("FWB", "F"),
+        ("LSE", "L"),
+        ("NASDAQ", ""),
+        ("NYSE", ""),
+        ("NYSEARCA", ""),
+        ("XETRA", "DE"),
+    ]
+    .into_iter()
+    .map(|(namespace, suffix)| (namespace.to_owned(), suffix.to_owned()))
+    .collect();
+
+    for (namespace, suffix) in custom_namespaces {
+        namespaces.insert(namespace.to_uppercase(), suffix.to_owned());
+    }
+
+    namespaces
+}
+
+/// The symbol as used by Yahoo, i.e. EL4X.DE for XETRA:EL4X.
+/// Symbols without a namespace are used as they are.
+pub(crate) fn get_local_symbol(
+    namespaces: &HashMap<String, String>,
+    symbol: &SecuritySymbol,
+) -> Result<String> {
+    if symbol.namespace.is_empty() {
+        return Ok(symbol.mnemonic.to_owned());
+    }
+
+    let suffix = namespaces.get(&symbol.namespace).ok_or_else(|| {
+        anyhow!(
+            "Unknown namespace {} for Yahoo Finance ({}). Add it to yahoo_namespaces in the configuration.",
+            symbol.namespace,
+            symbol
+        )
+    })?;
+
+    if suffix.is_empty() {
+        Ok(symbol.mnemonic.to_owned())
+    } else {
+        Ok(format!("{}.{}", symbol.mnemonic, suffix))
     }
 }
 
 /// # Tests
 #[cfg(test)]
 mod tests {
-    use chrono::{NaiveDateTime, TimeZone, FixedOffset};
+    use std::collections::HashMap;
+
+    use chrono::{DateTime, TimeZone, FixedOffset};
+    use serde_json::Value;
 
     use crate::quote::Downloader;
     use crate::{model::SecuritySymbol, quote::yahoo_finance_downloader::YahooFinanceDownloader};
@@ -166,7 +281,7 @@ mod tests {
             mnemonic: "EL4X".to_string(),
         };
 
-        let first = x.assemble_url(&s);
+        let first = x.assemble_url(&s).unwrap();
         assert_eq!(
             "https://query1.finance.yahoo.com/v8/finance/chart/EL4X.DE",
             first
@@ -181,13 +296,114 @@ mod tests {
             mnemonic: "BND".to_string(),
         };
 
-        let first = x.assemble_url(&s);
+        let first = x.assemble_url(&s).unwrap();
         assert_eq!(
             "https://query1.finance.yahoo.com/v8/finance/chart/BND",
             first
         );
     }
 
+    #[test]
+    fn test_assemble_url_configured() {
+        let custom = HashMap::from([("six".to_string(), "SW".to_string())]);
+        let mut x = YahooFinanceDownloader::new();
+        x.set_namespaces(&custom);
+
+        let actual = x.assemble_url(&SecuritySymbol::new("SIX:NESN")).unwrap();
+
+        assert_eq!("https://query1.finance.yahoo.com/v8/finance/chart/NESN.SW", actual);
+        // the defaults still apply
+        assert!(x.assemble_url(&SecuritySymbol::new("XETRA:EL4X")).is_ok());
+    }
+
+    #[test]
+    fn test_assemble_url_unknown_namespace() {
+        let x = YahooFinanceDownloader::new();
+
+        let actual = x.assemble_url(&SecuritySymbol::new("TSX:XIU"));
+
+        let error = actual.expect_err("unknown namespace");
+        assert!(error.to_string().starts_with("Unknown namespace TSX"));
+    }
+
+    #[test]
+    fn test_price_from_json() {
+        let body: Value = serde_json::from_str(
+            r#"{"chart": {"result": [{"meta": {"currency": "EUR", "symbol": "EL4X.DE",
+                "regularMarketPrice": 13.244, "regularMarketTime": 1670429622, "gmtoffset": 3600}}],
+                "error": null}}"#,
+        )
+        .unwrap();
+        let x = YahooFinanceDownloader::new();
+
+        let actual = x.get_price_from_json(&body).expect("parsed price");
+
+        assert_eq!("13.244", actual.value.to_string());
+        assert_eq!("EUR", actual.currency);
+        assert_eq!("2022-12-07 17:13:42", actual.datetime.to_string());
+        assert_eq!(FixedOffset::east_opt(3600), actual.offset);
+    }
+
+    #[test]
+    fn test_invalid_price_json() {
+        let x = YahooFinanceDownloader::new();
+        let parse = |json: &str| x.get_price_from_json(&serde_json::from_str(json).unwrap());
+
+        let error = parse(r#"{"chart": {"result": null, "error": {"code": "Not Found", "description": "No data found"}}}"#)
+            .expect_err("Yahoo error");
+        assert!(error.to_string().contains("No data found"));
+        assert!(parse(r#"{"chart": {"result": [], "error": null}}"#).is_err());
+        assert!(parse(
+            r#"{"chart": {"result": [{"meta": {"currency": "EUR", "regularMarketPrice": 13.244}}], "error": null}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_assemble_events_url() {
+        let x = YahooFinanceDownloader::new();
+        let from = chrono::NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let to = chrono::NaiveDate::from_ymd_opt(2024, 6, 30).unwrap();
+
+        let actual = x.assemble_events_url(&SecuritySymbol::new("NASDAQ:NVDA"), from, to).unwrap();
+
+        assert_eq!(
+            "https://query1.finance.yahoo.com/v8/finance/chart/NVDA?period1=1717200000&period2=1719792000&interval=1d&events=div,splits",
+            actual
+        );
+    }
+
+    #[test]
+    fn test_actions_from_json() {
+        let body: Value = serde_json::from_str(
+            r#"{"chart": {"result": [{"meta": {"currency": "USD", "symbol": "NVDA", "gmtoffset": -14400},
+                "events": {
+                    "dividends": {"1718112600": {"amount": 0.01, "date": 1718112600}},
+                    "splits": {"1718026200": {"date": 1718026200, "numerator": 10, "denominator": 1, "splitRatio": "10:1"}}
+                }}], "error": null}}"#,
+        )
+        .unwrap();
+
+        let actual = super::get_actions_from_json(&body, "NASDAQ:NVDA").unwrap();
+
+        assert_eq!(2, actual.len());
+        assert_eq!("2024-06-10 NASDAQ:NVDA split 10:1", actual[0].to_string());
+        assert_eq!("2024-06-11 NASDAQ:NVDA dividend 0.01 USD", actual[1].to_string());
+    }
+
+    #[test]
+    fn test_actions_in_pence() {
+        let body: Value = serde_json::from_str(
+            r#"{"chart": {"result": [{"meta": {"currency": "GBp", "symbol": "VOD.L", "gmtoffset": 3600},
+                "events": {"dividends": {"1718870400": {"amount": 4.5, "date": 1718870400}}}}], "error": null}}"#,
+        )
+        .unwrap();
+
+        let actual = super::get_actions_from_json(&body, "LSE:VOD").unwrap();
+
+        assert_eq!("2024-06-20 LSE:VOD dividend 4.5 GBp", actual[0].to_string());
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_download() {
         let o = YahooFinanceDownloader::new();
@@ -231,7 +447,7 @@ mod tests {
         // let ts_millis = NaiveDateTime::from_timestamp_millis(seconds).unwrap();
         // println!("millis: {:?}", ts_millis);
         
-        let ndt_ts_opt = NaiveDateTime::from_timestamp_opt(secs, 0).unwrap();
+        let ndt_ts_opt = DateTime::from_timestamp(secs, 0).unwrap().naive_utc();
         // println!("opts: {:?}", ts_opts);
         assert_eq!(ndt_ts_opt.to_string(), "2022-12-07 16:13:42");
 
@@ -1,7 +1,419 @@
-/*
- Url:
- https://query2.finance.yahoo.com/v10/finance/quoteSummary/AAPL?modules=price
-
- Source: 
- https://stackoverflow.com/questions/44030983/yahoo-finance-url-not-working
+/*!
+ * Yahoo Finance downloader using the quoteSummary (v10) endpoint.
+ * An alternative for when the chart (v8) endpoint, used by `YahooFinanceDownloader`,
+ * does not work. Selected with the `yahoo_quote_summary` agent.
+ *
+ * Url:
+ * https://query2.finance.yahoo.com/v10/finance/quoteSummary/AAPL?modules=price,quoteType
+ *
+ * The price module has the price and the time, and the quoteType module the
+ * exchange's UTC offset. The times are in the exchange's local time.
+ *
+ * Several symbols are downloaded with a single request to the quote (v7) endpoint:
+ * https://query2.finance.yahoo.com/v7/finance/quote?symbols=AAPL,EL4X.DE
+ *
+ * The endpoints require a cookie and a crumb. The cookie is set by any request to
+ * fc.yahoo.com, and the crumb is then retrieved with that cookie from /v1/test/getcrumb.
+ *
+ * Source:
+ * https://stackoverflow.com/questions/44030983/yahoo-finance-url-not-working
  */
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use reqwest::{
+    header::{HeaderMap, COOKIE, SET_COOKIE, USER_AGENT},
+    Client, Url,
+};
+use serde_json::Value;
+
+use crate::model::{Price, SecuritySymbol};
+
+use super::{
+    parse_decimal,
+    yahoo_finance_downloader::{get_local_symbol, get_namespaces},
+    Downloader,
+};
+
+const COOKIE_URL: &str = "https://fc.yahoo.com";
+const QUOTE_URL: &str = "https://query2.finance.yahoo.com/v7/finance/quote";
+const CRUMB_URL: &str = "https://query1.finance.yahoo.com/v1/test/getcrumb";
+const USER_AGENT_VALUE: &str =
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:124.0) Gecko/20100101 Firefox/124.0";
+
+pub struct YahooQuoteSummaryDownloader {
+    url: String,
+    namespaces: HashMap<String, String>,
+}
+
+/// The session cookie and the crumb, required for the quoteSummary requests.
+#[derive(Debug)]
+struct Session {
+    cookie: String,
+    crumb: String,
+}
+
+impl YahooQuoteSummaryDownloader {
+    pub fn new() -> Self {
+        Self {
+            url: "https://query2.finance.yahoo.com/v10/finance/quoteSummary/".to_string(),
+            namespaces: get_namespaces(&HashMap::new()),
+        }
+    }
+
+    /// Applies the given namespace -> suffix mapping over the built-in one.
+    pub fn set_namespaces(&mut self, custom_namespaces: &HashMap<String, String>) {
+        self.namespaces = get_namespaces(custom_namespaces);
+    }
+
+    fn assemble_url(&self, symbol: &SecuritySymbol, crumb: &str) -> Result<String> {
+        let local_symbol = get_local_symbol(&self.namespaces, symbol)?;
+
+        Ok(format!("{}{}?modules=price,quoteType&crumb={}", self.url, local_symbol, crumb))
+    }
+
+    fn get_client(&self) -> Result<Client> {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, USER_AGENT_VALUE.parse()?);
+
+        Ok(Client::builder().default_headers(headers).build()?)
+    }
+
+    /// The cookie/crumb handshake.
+    async fn get_session(&self, client: &Client) -> Result<Session> {
+        // The response status is irrelevant (usually 404), only the cookie is needed.
+        let response = client.get(COOKIE_URL).send().await?;
+        let cookie = get_cookie(response.headers())
+            .ok_or_else(|| anyhow!("Yahoo did not set the session cookie"))?;
+
+        let response = client.get(CRUMB_URL).header(COOKIE, &cookie).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Could not retrieve the Yahoo crumb: {}", response.status()));
+        }
+        let crumb = response.text().await?.trim().to_owned();
+        if crumb.is_empty() || crumb.contains(' ') {
+            return Err(anyhow!("Invalid Yahoo crumb: {crumb}"));
+        }
+
+        log::debug!("Yahoo crumb: {crumb}");
+
+        Ok(Session { cookie, crumb })
+    }
+
+    /// Extract the Price from the quoteSummary JSON.
+    fn get_price_from_json(&self, body: &Value) -> Result<Price> {
+        let summary = &body["quoteSummary"];
+        let error = &summary["error"];
+        if !error.is_null() {
+            return Err(anyhow!("Yahoo error: {}", error["description"]));
+        }
+
+        let result_node = &summary["result"][0];
+        let price = &result_node["price"];
+        if price.is_null() {
+            return Err(anyhow!("No price in the Yahoo response"));
+        }
+
+        let mut result = Price::new();
+
+        // The values are objects, i.e. { "raw": 13.244, "fmt": "13.24" }.
+        result.value = parse_decimal(&price["regularMarketPrice"]["raw"])
+            .context("Invalid regularMarketPrice")?;
+
+        // As reported. The LSE prices are in pence (GBp).
+        result.currency = price["currency"]
+            .as_str()
+            .ok_or_else(|| anyhow!("No currency in the Yahoo response"))?
+            .to_owned();
+
+        let (datetime, offset) = get_exchange_datetime(
+            &price["regularMarketTime"],
+            &result_node["quoteType"]["gmtOffSetMilliseconds"],
+        )?;
+        result.datetime = datetime;
+        result.offset = Some(offset);
+
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl Downloader for YahooQuoteSummaryDownloader {
+    async fn download(&self, security_symbol: &SecuritySymbol, _currency: &str) -> Result<Price> {
+        let client = self.get_client()?;
+
+        let session = self.get_session(&client).await?;
+        let url = self.assemble_url(security_symbol, &session.crumb)?;
+
+        log::debug!("fetching from {:?}", url);
+
+        let body = client
+            .get(url)
+            .header(COOKIE, &session.cookie)
+            .send()
+            .await?
+            .json::<Value>()
+            .await?;
+
+        self.get_price_from_json(&body)
+    }
+
+    /// All the symbols in one request to the quote endpoint.
+    async fn download_batch(
+        &self,
+        symbols: &[SecuritySymbol],
+        _currency: &str,
+    ) -> Vec<Result<Price>> {
+        let local_symbols: Vec<Result<String>> = symbols
+            .iter()
+            .map(|symbol| get_local_symbol(&self.namespaces, symbol))
+            .collect();
+        let requested: Vec<&str> = local_symbols
+            .iter()
+            .filter_map(|symbol| symbol.as_deref().ok())
+            .collect();
+
+        let quotes = match self.download_quotes(&requested).await {
+            Ok(quotes) => quotes,
+            Err(error) => {
+                return symbols
+                    .iter()
+                    .map(|_| Err(anyhow!("Yahoo quote request failed: {error:#}")))
+                    .collect()
+            }
+        };
+
+        local_symbols
+            .into_iter()
+            .map(|local_symbol| {
+                let local_symbol = local_symbol?;
+                match quotes.get(&local_symbol.to_uppercase()) {
+                    Some(Ok(price)) => Ok(price.clone()),
+                    Some(Err(error)) => Err(anyhow!("Invalid Yahoo quote for {local_symbol}: {error}")),
+                    None => Err(anyhow!("No Yahoo quote for {local_symbol}")),
+                }
+            })
+            .collect()
+    }
+}
+
+impl YahooQuoteSummaryDownloader {
+    /// The prices per (upper-case) Yahoo symbol.
+    async fn download_quotes(&self, local_symbols: &[&str]) -> Result<HashMap<String, Result<Price>>> {
+        if local_symbols.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let client = self.get_client()?;
+        let session = self.get_session(&client).await?;
+        let url = get_quotes_url(local_symbols, &session.crumb)?;
+
+        log::debug!("fetching from {:?}", url);
+
+        let body = client
+            .get(url)
+            .header(COOKIE, &session.cookie)
+            .send()
+            .await?
+            .json::<Value>()
+            .await?;
+
+        get_prices_from_quotes(&body)
+    }
+}
+
+/// The quote (v7) url. The symbols, i.e. ^GSPC or EURUSD=X, are encoded.
+fn get_quotes_url(local_symbols: &[&str], crumb: &str) -> Result<Url> {
+    let url = Url::parse_with_params(
+        QUOTE_URL,
+        &[("symbols", local_symbols.join(",").as_str()), ("crumb", crumb)],
+    )?;
+    Ok(url)
+}
+
+/// Reads the prices from the quote (v7) response, per symbol. The time is the local
+/// time of the exchange.
+fn get_prices_from_quotes(body: &Value) -> Result<HashMap<String, Result<Price>>> {
+    let response = &body["quoteResponse"];
+    if !response["error"].is_null() {
+        return Err(anyhow!("Yahoo error: {}", response["error"]["description"]));
+    }
+    let quotes = response["result"]
+        .as_array()
+        .ok_or_else(|| anyhow!("No quotes in the Yahoo response"))?;
+
+    let get_price = |quote: &Value| -> Result<Price> {
+        let (datetime, offset) =
+            get_exchange_datetime(&quote["regularMarketTime"], &quote["gmtOffSetMilliseconds"])?;
+
+        Ok(Price {
+            datetime,
+            offset: Some(offset),
+            value: parse_decimal(&quote["regularMarketPrice"]).context("Invalid regularMarketPrice")?,
+            currency: quote["currency"]
+                .as_str()
+                .ok_or_else(|| anyhow!("No currency in the Yahoo quote"))?
+                .to_owned(),
+            ..Price::default()
+        })
+    };
+
+    Ok(quotes
+        .iter()
+        .filter_map(|quote| {
+            let symbol = quote["symbol"].as_str()?.to_uppercase();
+            Some((symbol, get_price(quote)))
+        })
+        .collect())
+}
+
+/// The market time in the exchange's local time, from the (UTC) timestamp and the
+/// exchange's `gmtOffSetMilliseconds`. In UTC, if the offset is missing.
+fn get_exchange_datetime(time: &Value, offset_ms: &Value) -> Result<(NaiveDateTime, FixedOffset)> {
+    let seconds = time
+        .as_i64()
+        .ok_or_else(|| anyhow!("No regularMarketTime in the Yahoo response"))?;
+    let offset_ms = offset_ms.as_i64().unwrap_or_default();
+    let offset = FixedOffset::east_opt((offset_ms / 1000) as i32)
+        .ok_or_else(|| anyhow!("Invalid gmtOffSetMilliseconds {offset_ms}"))?;
+    let datetime = DateTime::from_timestamp(seconds, 0)
+        .ok_or_else(|| anyhow!("Invalid regularMarketTime {seconds}"))?
+        .with_timezone(&offset);
+
+    Ok((datetime.naive_local(), offset))
+}
+
+/// The cookies from the Set-Cookie headers, as the value for the Cookie header.
+fn get_cookie(headers: &HeaderMap) -> Option<String> {
+    let cookies: Vec<&str> = headers
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| value.split(';').next())
+        .map(str::trim)
+        .filter(|cookie| !cookie.is_empty())
+        .collect();
+
+    if cookies.is_empty() {
+        None
+    } else {
+        Some(cookies.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_assemble_url() {
+        let x = YahooQuoteSummaryDownloader::new();
+
+        let actual = x.assemble_url(&SecuritySymbol::new("XETRA:EL4X"), "abc").unwrap();
+
+        assert_eq!(
+            "https://query2.finance.yahoo.com/v10/finance/quoteSummary/EL4X.DE?modules=price,quoteType&crumb=abc",
+            actual
+        );
+    }
+
+    #[test]
+    fn test_quotes_url() {
+        let actual = get_quotes_url(&["^GSPC", "EURUSD=X"], "a/b").unwrap();
+
+        assert_eq!(
+            "https://query2.finance.yahoo.com/v7/finance/quote?symbols=%5EGSPC%2CEURUSD%3DX&crumb=a%2Fb",
+            actual.as_str()
+        );
+    }
+
+    #[test]
+    fn test_prices_from_quotes() {
+        let body: Value = serde_json::from_str(
+            r#"{"quoteResponse": {"error": null, "result": [
+                {"symbol": "EL4X.DE", "currency": "EUR", "regularMarketPrice": 13.244,
+                 "regularMarketTime": 1706718914, "gmtOffSetMilliseconds": 3600000},
+                {"symbol": "BND", "currency": "USD", "regularMarketPrice": 72.81,
+                 "regularMarketTime": 1706734800, "gmtOffSetMilliseconds": -18000000},
+                {"symbol": "VOD.L", "currency": "GBp", "regularMarketPrice": 69.5,
+                 "regularMarketTime": 1706718600, "gmtOffSetMilliseconds": 0},
+                {"symbol": "VHY.AX", "currency": "AUD"}]}}"#,
+        )
+        .unwrap();
+
+        let actual = get_prices_from_quotes(&body).unwrap();
+
+        let el4x = actual["EL4X.DE"].as_ref().unwrap();
+        assert_eq!("13.244", el4x.value.to_string());
+        assert_eq!("EUR", el4x.currency);
+        assert_eq!("2024-01-31 17:35:14", el4x.datetime.to_string());
+        assert_eq!(FixedOffset::east_opt(3600), el4x.offset);
+        assert_eq!("2024-01-31 16:00:00", actual["BND"].as_ref().unwrap().datetime.to_string());
+        // in pence
+        assert_eq!("GBp", actual["VOD.L"].as_ref().unwrap().currency);
+        assert!(actual["VHY.AX"].is_err());
+    }
+
+    #[test]
+    fn test_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            SET_COOKIE,
+            HeaderValue::from_static("A3=d=AQABBK; Expires=Wed, 05 Feb 2025 12:00:00 GMT; Domain=.yahoo.com"),
+        );
+        headers.append(SET_COOKIE, HeaderValue::from_static("A1=d=AQABBK; Path=/"));
+
+        assert_eq!(Some("A3=d=AQABBK; A1=d=AQABBK".to_string()), get_cookie(&headers));
+        assert_eq!(None, get_cookie(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_price_from_json() {
+        let body: Value =
+            serde_json::from_str(include_str!("../../tests/yahoo_quote_summary.json")).unwrap();
+        let x = YahooQuoteSummaryDownloader::new();
+
+        let actual = x.get_price_from_json(&body).expect("parsed price");
+
+        assert_eq!("13.244", actual.value.to_string());
+        assert_eq!("EUR", actual.currency);
+        // at the exchange
+        assert_eq!("2022-12-07 17:13:42", actual.datetime.to_string());
+        assert_eq!(FixedOffset::east_opt(3600), actual.offset);
+    }
+
+    #[test]
+    fn test_price_in_pence() {
+        let body: Value = serde_json::from_str(
+            r#"{"quoteSummary": {"error": null, "result": [{
+                "price": {"currency": "GBp", "symbol": "VOD.L", "regularMarketPrice": {"raw": 69.5, "fmt": "69.50"},
+                    "regularMarketTime": 1706718600},
+                "quoteType": {"gmtOffSetMilliseconds": 0}}]}}"#,
+        )
+        .unwrap();
+        let x = YahooQuoteSummaryDownloader::new();
+
+        let actual = x.get_price_from_json(&body).expect("parsed price");
+
+        assert_eq!("69.5", actual.value.to_string());
+        assert_eq!("GBp", actual.currency);
+    }
+
+    #[test]
+    fn test_error_from_json() {
+        let body: Value = serde_json::from_str(
+            r#"{"quoteSummary": {"result": null, "error": {"code": "Not Found",
+                "description": "Quote not found for ticker symbol: XYZ.DE"}}}"#,
+        )
+        .unwrap();
+        let x = YahooQuoteSummaryDownloader::new();
+
+        let actual = x.get_price_from_json(&body);
+
+        assert!(actual.unwrap_err().to_string().contains("Quote not found"));
+    }
+}
@@ -0,0 +1,237 @@
+/*!
+ * Output formats for the stored prices and corporate actions.
+ */
+use std::str::FromStr;
+
+use serde_json::{json, Number, Value};
+
+use crate::{model::CorporateAction, price_flat_file::PriceRecord};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    /// Ledger price directives, as in the price file
+    Ledger,
+    Csv,
+    Json,
+}
+
+/// Formats the price records for output.
+pub fn format_prices(records: &[PriceRecord], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Table => format_table(records),
+        OutputFormat::Ledger => records
+            .iter()
+            .map(|record| format!("{record}\n"))
+            .collect(),
+        OutputFormat::Csv => format_csv(records),
+        OutputFormat::Json => {
+            let list: Vec<Value> = records.iter().map(to_json).collect();
+            format!("{:#}\n", Value::Array(list))
+        }
+    }
+}
+
+/// The price as a JSON object. The value is a number, with the exact decimals.
+pub fn to_json(record: &PriceRecord) -> Value {
+    let value = Number::from_str(&record.value.to_string()).expect("valid number");
+
+    json!({
+        "symbol": record.symbol,
+        "date": record.datetime.date().to_string(),
+        "time": record.datetime.time().to_string(),
+        "value": value,
+        "currency": record.currency,
+    })
+}
+
+fn format_table(records: &[PriceRecord]) -> String {
+    let rows: Vec<[String; 5]> = records
+        .iter()
+        .map(|record| {
+            [
+                record.datetime.date().to_string(),
+                record.datetime.time().to_string(),
+                record.symbol.to_owned(),
+                record.value.to_string(),
+                record.currency.to_owned(),
+            ]
+        })
+        .collect();
+
+    format_columns(["Date", "Time", "Symbol", "Value", "Currency"], &rows)
+}
+
+/// Aligns the columns. The values (4th column) are aligned to the right.
+fn format_columns(header: [&str; 5], rows: &[[String; 5]]) -> String {
+    let header = header.map(String::from);
+    let mut widths = header.clone().map(|title| title.len());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut output = String::default();
+    for row in std::iter::once(&header).chain(rows) {
+        let line = format!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {:>w3$}  {:<w4$}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+            w4 = widths[4],
+        );
+        output += line.trim_end();
+        output += "\n";
+    }
+
+    output
+}
+
+fn format_csv(records: &[PriceRecord]) -> String {
+    let mut output = "date,time,symbol,value,currency\n".to_string();
+
+    for record in records {
+        output += &format!(
+            "{},{},{},{},{}\n",
+            record.datetime.date(),
+            record.datetime.time(),
+            record.symbol,
+            record.value,
+            record.currency
+        );
+    }
+
+    output
+}
+
+/// Formats the corporate actions for output. The Ledger format has the actions as
+/// comments.
+pub fn format_actions(actions: &[CorporateAction], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Table => {
+            let rows: Vec<[String; 5]> = actions
+                .iter()
+                .map(|action| {
+                    [
+                        action.date.to_string(),
+                        action.symbol.to_owned(),
+                        action.kind.name().to_owned(),
+                        action.kind.value(),
+                        action.kind.currency().to_owned(),
+                    ]
+                })
+                .collect();
+            format_columns(["Date", "Symbol", "Action", "Value", "Currency"], &rows)
+        }
+        OutputFormat::Ledger => actions
+            .iter()
+            .map(|action| format!("; {action}\n"))
+            .collect(),
+        OutputFormat::Csv => {
+            let mut output = "date,symbol,action,value,currency\n".to_string();
+            for action in actions {
+                output += &format!(
+                    "{},{},{},{},{}\n",
+                    action.date,
+                    action.symbol,
+                    action.kind.name(),
+                    action.kind.value(),
+                    action.kind.currency()
+                );
+            }
+            output
+        }
+        OutputFormat::Json => {
+            let list: Vec<Value> = actions
+                .iter()
+                .map(|action| {
+                    json!({
+                        "date": action.date.to_string(),
+                        "symbol": action.symbol,
+                        "action": action.kind.name(),
+                        "value": action.kind.value(),
+                        "currency": action.kind.currency(),
+                    })
+                })
+                .collect();
+            format!("{:#}\n", Value::Array(list))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::price_flat_file::PriceReader;
+
+    use super::*;
+
+    fn records() -> Vec<PriceRecord> {
+        let content = "P 2023-04-14 GBP 1.132283 EUR\nP 2023-04-15 12:00:00 VEUR_AS 1.5 EUR\n";
+
+        PriceReader::new(content.as_bytes())
+            .collect::<anyhow::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_table() {
+        let actual = format_prices(&records(), OutputFormat::Table);
+
+        assert_eq!(
+            "Date        Time      Symbol      Value  Currency
+2023-04-14  00:00:00  GBP      1.132283  EUR
+2023-04-15  12:00:00  VEUR_AS       1.5  EUR
+",
+            actual
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        let actual = format_prices(&records(), OutputFormat::Csv);
+
+        assert_eq!(
+            "date,time,symbol,value,currency
+2023-04-14,00:00:00,GBP,1.132283,EUR
+2023-04-15,12:00:00,VEUR_AS,1.5,EUR
+",
+            actual
+        );
+    }
+
+    #[test]
+    fn test_actions() {
+        let actions = vec![CorporateAction {
+            symbol: "NVDA".into(),
+            date: chrono::NaiveDate::from_ymd_opt(2024, 6, 10).unwrap(),
+            kind: crate::model::CorporateActionKind::Split {
+                numerator: 10.into(),
+                denominator: 1.into(),
+            },
+        }];
+
+        assert_eq!(
+            "Date        Symbol  Action  Value  Currency\n2024-06-10  NVDA    split    10:1\n",
+            format_actions(&actions, OutputFormat::Table)
+        );
+        assert_eq!("; 2024-06-10 NVDA split 10:1\n", format_actions(&actions, OutputFormat::Ledger));
+    }
+
+    #[test]
+    fn test_json_keeps_decimals() {
+        let actual = to_json(&records()[0]);
+
+        assert_eq!(
+            r#"{"currency":"EUR","date":"2023-04-14","symbol":"GBP","time":"00:00:00","value":1.132283}"#,
+            actual.to_string()
+        );
+    }
+}
@@ -0,0 +1,524 @@
+/*!
+ * Local HTTP API, serving the stored prices as JSON. Read-only.
+ *
+ * GET /symbols                              the securities in the symbols file
+ * GET /prices?currency=&agent=&exchange=    the latest price of each symbol
+ * GET /prices/{symbol}                      the latest price of the symbol
+ * GET /prices/{symbol}/history?from=&to=    the prices of the symbol in the date range
+ * GET /convert?amount=&from=&to=&date=      conversion between two commodities on a date
+ *
+ * The symbol is the ledger symbol, as in the price file, and the dates are YYYY-MM-DD.
+ * Errors are returned as {"error": "..."}, with the 4xx/5xx status.
+ */
+use std::{
+    collections::HashMap, fmt::Display, net::SocketAddr, str::FromStr, sync::Arc, time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde_json::{json, Number, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    convert::Conversion,
+    load_symbols,
+    model::{PriceQuery, SecurityFilter},
+    report, App,
+};
+
+/// The request headers are read up to this size. The rest is ignored.
+const MAX_HEAD_SIZE: usize = 8192;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// The decoded path, without the query.
+    pub path: String,
+    pub query: HashMap<String, String>,
+}
+
+impl Request {
+    /// Parses the request line, i.e. GET /prices/VEUR_AS/history?from=2023-01-01 HTTP/1.1
+    pub fn parse(head: &str) -> Result<Self> {
+        let line = head.lines().next().unwrap_or_default();
+        let [method, target, version] = line.split_whitespace().collect::<Vec<&str>>()[..] else {
+            return Err(anyhow!("Invalid request line {line}"));
+        };
+        if !version.starts_with("HTTP/") {
+            return Err(anyhow!("Invalid request line {line}"));
+        }
+
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode(name), decode(value))
+            })
+            .collect();
+
+        Ok(Self {
+            method: method.to_owned(),
+            path: decode(path),
+            query,
+        })
+    }
+
+    /// The query parameter. Empty values count as missing.
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .get(name)
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
+
+    fn date_param(&self, name: &str) -> Result<Option<NaiveDate>, Response> {
+        self.param(name)
+            .map(|value| {
+                NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+                    Response::error(400, format!("Invalid {name} date {value}, expected YYYY-MM-DD"))
+                })
+            })
+            .transpose()
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl Response {
+    fn error(status: u16, message: impl Display) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message.to_string() }),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+
+    fn to_http(&self) -> String {
+        let body = self.body.to_string();
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.status,
+            self.reason(),
+            body.len()
+        )
+    }
+}
+
+impl From<anyhow::Error> for Response {
+    fn from(error: anyhow::Error) -> Self {
+        Self::error(500, format!("{error:#}"))
+    }
+}
+
+type Handled = Result<Value, Response>;
+
+pub struct Server {
+    app: App,
+    symbols_path: Option<String>,
+    price_path: Option<String>,
+}
+
+impl Server {
+    /// The server for the given files, or the configured ones.
+    pub fn new(app: &App, symbols_path: &Option<String>, price_path: &Option<String>) -> Self {
+        Self {
+            app: app.clone(),
+            symbols_path: symbols_path.to_owned(),
+            price_path: price_path.to_owned(),
+        }
+    }
+
+    /// Serves the requests until stopped with Ctrl-C. Each connection is served in its
+    /// own task, so that a slow client or a wait for the price file does not block the others.
+    pub async fn run(self, bind: &str, port: u16) -> Result<()> {
+        let listener = TcpListener::bind((bind, port))
+            .await
+            .with_context(|| format!("Could not listen on {bind}:{port}"))?;
+        println!("Serving the prices on http://{}. Stop with Ctrl-C.", listener.local_addr()?);
+
+        let server = Arc::new(self);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, peer) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(error) => {
+                            log::warn!("Could not accept a connection: {error}");
+                            continue;
+                        }
+                    };
+                    Self::spawn_connection(&server, stream, peer);
+                }
+                _ = tokio::signal::ctrl_c() => {
+                    println!("Stopped.");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn spawn_connection(server: &Arc<Self>, stream: TcpStream, peer: SocketAddr) {
+        let server = Arc::clone(server);
+        tokio::spawn(async move {
+            if let Err(error) = server.serve_connection(stream).await {
+                log::warn!("{peer}: {error:#}");
+            }
+        });
+    }
+
+    async fn serve_connection(self: Arc<Self>, mut stream: TcpStream) -> Result<()> {
+        let head = tokio::time::timeout(READ_TIMEOUT, read_head(&mut stream))
+            .await
+            .context("Timed out reading the request")??;
+
+        let response = match Request::parse(&head) {
+            // The files are read with blocking I/O, and may wait for the lock.
+            Ok(request) => tokio::task::spawn_blocking(move || {
+                let response = self.handle(&request);
+                log::info!("{} {} {}", request.method, request.path, response.status);
+                response
+            })
+            .await
+            .unwrap_or_else(|error| Response::error(500, error)),
+            Err(error) => Response::error(400, error),
+        };
+
+        let write = async {
+            stream.write_all(response.to_http().as_bytes()).await?;
+            stream.shutdown().await
+        };
+        tokio::time::timeout(WRITE_TIMEOUT, write)
+            .await
+            .context("Timed out writing the response")??;
+
+        Ok(())
+    }
+
+    /// Routes the request.
+    pub fn handle(&self, request: &Request) -> Response {
+        if request.method != "GET" {
+            return Response::error(405, format!("Method {} not allowed", request.method));
+        }
+
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let handled = match segments[..] {
+            ["symbols"] => self.symbols(),
+            ["prices"] => self.latest_prices(request),
+            ["prices", symbol] => self.latest_price(symbol),
+            ["prices", symbol, "history"] => self.history(symbol, request),
+            ["convert"] => self.convert(request),
+            _ => Err(Response::error(404, format!("Unknown path {}", request.path))),
+        };
+
+        match handled {
+            Ok(body) => Response { status: 200, body },
+            Err(response) => response,
+        }
+    }
+
+    fn symbols(&self) -> Handled {
+        let (symbols_path, _) = self.app.get_quote_params(&self.symbols_path, &None);
+        let securities = load_symbols(&symbols_path)?;
+
+        let list = securities
+            .iter()
+            .map(|sec| {
+                json!({
+                    "ledger_symbol": sec.get_symbol(),
+                    "namespace": sec.namespace,
+                    "symbol": sec.symbol,
+                    "currency": sec.currency,
+                    "agent": sec.updater,
+                })
+            })
+            .collect();
+
+        Ok(Value::Array(list))
+    }
+
+    fn latest_prices(&self, request: &Request) -> Handled {
+        let query = PriceQuery {
+            securities: SecurityFilter {
+                currency: request.param("currency").map(str::to_owned),
+                agent: request.param("agent").map(str::to_owned),
+                exchange: request.param("exchange").map(str::to_owned),
+                symbol: None,
+            },
+            ..PriceQuery::default()
+        };
+
+        self.query(&query)
+    }
+
+    fn latest_price(&self, symbol: &str) -> Handled {
+        let mut query = PriceQuery::default();
+        query.prices.symbol = Some(symbol.to_owned());
+
+        match self.query(&query)? {
+            Value::Array(mut prices) if !prices.is_empty() => Ok(prices.remove(0)),
+            _ => Err(Response::error(404, format!("No price for {}", symbol.to_uppercase()))),
+        }
+    }
+
+    fn history(&self, symbol: &str, request: &Request) -> Handled {
+        let mut query = PriceQuery {
+            from: request.date_param("from")?,
+            to: request.date_param("to")?,
+            history: true,
+            ..PriceQuery::default()
+        };
+        query.prices.symbol = Some(symbol.to_owned());
+
+        self.query(&query)
+    }
+
+    fn query(&self, query: &PriceQuery) -> Handled {
+        let records = self
+            .app
+            .query_prices(&self.symbols_path, &self.price_path, query)?;
+
+        Ok(Value::Array(records.iter().map(report::to_json).collect()))
+    }
+
+    fn convert(&self, request: &Request) -> Handled {
+        let (Some(from), Some(to)) = (request.param("from"), request.param("to")) else {
+            return Err(Response::error(400, "The from and to commodities are required"));
+        };
+        let amount = request.param("amount").unwrap_or("1");
+        let date = request.date_param("date")?;
+
+        let conversion = self
+            .app
+            .convert(&self.price_path, amount, from, to, date)
+            .map_err(|error| Response::error(400, format!("{error:#}")))?;
+
+        Ok(conversion_to_json(&conversion))
+    }
+}
+
+fn conversion_to_json(conversion: &Conversion) -> Value {
+    let steps: Vec<Value> = conversion
+        .steps
+        .iter()
+        .map(|step| {
+            json!({
+                "from": step.from,
+                "to": step.to,
+                "rate": number(step.rate),
+                "date": step.datetime.date().to_string(),
+                "time": step.datetime.time().to_string(),
+                "inverse": step.inverse,
+            })
+        })
+        .collect();
+
+    json!({
+        "amount": number(conversion.amount),
+        "from": conversion.from,
+        "to": conversion.to,
+        "result": number(conversion.result),
+        "steps": steps,
+    })
+}
+
+/// The exact decimal, as a JSON number.
+fn number(value: Decimal) -> Value {
+    Value::Number(Number::from_str(&value.to_string()).expect("valid number"))
+}
+
+/// Reads the request line and the headers. GET requests have no body.
+async fn read_head(stream: &mut TcpStream) -> Result<String> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") && head.len() < MAX_HEAD_SIZE {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Percent-decodes the URL part. `+` is a space.
+fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let escaped = text
+            .get(index + 1..index + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[index], escaped) {
+            (b'+', _) => decoded.push(b' '),
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 2;
+            }
+            (byte, _) => decoded.push(byte),
+        }
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::PriceDbConfig;
+
+    use super::*;
+
+    fn app() -> App {
+        App::new(PriceDbConfig {
+            symbols_path: "tests/symbols.csv".into(),
+            prices_path: "tests/prices.txt".into(),
+            ..PriceDbConfig::default()
+        })
+    }
+
+    fn get(app: &App, target: &str) -> Response {
+        let request = Request::parse(&format!("GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n"))
+            .unwrap();
+        Server::new(app, &None, &None).handle(&request)
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let request =
+            Request::parse("GET /convert?amount=1%2C000&from=gbp&to=usd&date= HTTP/1.1\r\n\r\n")
+                .unwrap();
+
+        assert_eq!("GET", request.method);
+        assert_eq!("/convert", request.path);
+        assert_eq!(Some("1,000"), request.param("amount"));
+        assert_eq!(None, request.param("date"));
+        assert!(Request::parse("GET /prices").is_err());
+        assert_eq!("a b%z", decode("a+b%z"));
+    }
+
+    #[test]
+    fn test_prices() {
+        let app = app();
+
+        let response = get(&app, "/prices/veur_as");
+        assert_eq!(200, response.status);
+        assert_eq!(
+            json!({"symbol": "VEUR_AS", "date": "2023-04-15", "time": "12:00:00", "value": 1.5, "currency": "EUR"}),
+            response.body
+        );
+
+        let response = get(&app, "/prices");
+        assert_eq!(3, response.body.as_array().unwrap().len());
+
+        let response = get(&app, "/prices/USD/history?from=2023-04-01");
+        assert_eq!(json!([]), response.body);
+
+        assert_eq!(404, get(&app, "/prices/VTI").status);
+        assert_eq!(400, get(&app, "/prices/USD/history?to=30.4.2023").status);
+    }
+
+    #[test]
+    fn test_convert_and_symbols() {
+        let app = app();
+
+        let response = get(&app, "/convert?amount=1000&from=gbp&to=usd&date=2023-04-30");
+        assert_eq!(200, response.status);
+        assert_eq!(2, response.body["steps"].as_array().unwrap().len());
+        assert_eq!(400, get(&app, "/convert?from=gbp").status);
+
+        let response = get(&app, "/symbols");
+        assert_eq!("EL4X_DE", response.body[2]["ledger_symbol"]);
+    }
+
+    #[test]
+    fn test_routing_errors() {
+        let app = app();
+        let request = Request::parse("POST /prices HTTP/1.1\r\n\r\n").unwrap();
+
+        assert_eq!(405, Server::new(&app, &None, &None).handle(&request).status);
+        assert_eq!(404, get(&app, "/quotes").status);
+
+        let app = App::new(PriceDbConfig {
+            symbols_path: "tests/missing.csv".into(),
+            prices_path: "tests/prices.txt".into(),
+            ..PriceDbConfig::default()
+        });
+        assert_eq!(500, get(&app, "/prices?currency=EUR").status);
+    }
+
+    #[tokio::test]
+    async fn test_serve_connection() {
+        let app = app();
+        let server = Arc::new(Server::new(&app, &None, &None));
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            stream
+                .write_all(b"GET /prices/VEUR_AS HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        server.serve_connection(stream).await.unwrap();
+        let response = client.await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(r#""symbol":"VEUR_AS","time":"12:00:00","value":1.5}"#));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_connections() {
+        let server = Arc::new(Server::new(&app(), &None, &None));
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // A client that does not send the request.
+        let _idle = TcpStream::connect(address).await.unwrap();
+        let (stream, peer) = listener.accept().await.unwrap();
+        Server::spawn_connection(&server, stream, peer);
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        let (stream, peer) = listener.accept().await.unwrap();
+        Server::spawn_connection(&server, stream, peer);
+        client.write_all(b"GET /symbols HTTP/1.1\r\n\r\n").await.unwrap();
+
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_string(&mut response))
+            .await
+            .expect("served while the other client is idle")
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+}
@@ -3,11 +3,13 @@ use rstest::fixture;
  * Integration tests
  */
 
+use chrono::NaiveDateTime;
 use pricedb::{
     config::PriceDbConfig,
     model::Price,
     App,
 };
+use rust_decimal::Decimal;
 
 /// Sets up an in-memory database.
 #[fixture]
@@ -25,10 +27,10 @@ fn new_price() -> Price {
     Price {
         symbol: "VTI".to_owned(),
         id: 0,
-        date: "2022-12-01".into(),
-        time: "13:25:44".into(),
-        value: 1033,
-        denom: 100,
+        datetime: NaiveDateTime::parse_from_str("2022-12-01 13:25:44", "%Y-%m-%d %H:%M:%S")
+            .unwrap(),
+        offset: None,
+        value: Decimal::new(1033, 2),
         currency: "EUR".into()
     }
 }